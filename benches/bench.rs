@@ -1,5 +1,13 @@
-use cornedbeef::CbHashMap;
-use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+#![feature(allocator_api)]
+
+use cornedbeef::group::{DetectedGroup, Group, SimdGroup};
+use cornedbeef::seventh::Map as RobinHoodMap;
+use cornedbeef::{fifth, CbHashMap, DefaultHashBuilder};
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion,
+};
+use std::alloc::Global;
 use std::collections::HashMap as StdHashMap;
 
 const SIZE: usize = 100_000;
@@ -96,12 +104,14 @@ pub fn insert_grow_seq(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_grow!(group, StdHashMap, "std", 0..SIZE, LEN);
         bench_grow!(group, CbHashMap, "cb", 0..SIZE, LEN);
+        bench_grow!(group, RobinHoodMap, "robin_hood", 0..SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_grow!(group, StdHashMap, "std", 0..SIZE, LEN);
         bench_grow!(group, CbHashMap, "cb", 0..SIZE, LEN);
+        bench_grow!(group, RobinHoodMap, "robin_hood", 0..SIZE, LEN);
     }
     group.finish();
 }
@@ -114,12 +124,14 @@ pub fn insert_grow_random(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_grow!(group, StdHashMap, "std", seq.take(SIZE), LEN);
         bench_grow!(group, CbHashMap, "cb", seq.take(SIZE), LEN);
+        bench_grow!(group, RobinHoodMap, "robin_hood", seq.take(SIZE), LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_grow!(group, StdHashMap, "std", seq.take(SIZE), LEN);
         bench_grow!(group, CbHashMap, "cb", seq.take(SIZE), LEN);
+        bench_grow!(group, RobinHoodMap, "robin_hood", seq.take(SIZE), LEN);
     }
     group.finish();
 }
@@ -149,12 +161,14 @@ pub fn insert_reserved(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_reserved!(group, StdHashMap, "std", seq.take(SIZE), SIZE, LEN);
         bench_reserved!(group, CbHashMap, "cb", seq.take(SIZE), SIZE, LEN);
+        bench_reserved!(group, RobinHoodMap, "robin_hood", seq.take(SIZE), SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_reserved!(group, StdHashMap, "std", seq.take(SIZE), SIZE, LEN);
         bench_reserved!(group, CbHashMap, "cb", seq.take(SIZE), SIZE, LEN);
+        bench_reserved!(group, RobinHoodMap, "robin_hood", seq.take(SIZE), SIZE, LEN);
     }
     group.finish();
 }
@@ -191,12 +205,14 @@ pub fn lookup(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_lookup!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_lookup!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     group.finish();
@@ -235,12 +251,14 @@ pub fn lookup_string(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_lookup_string!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup_string!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup_string!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_lookup_string!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup_string!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup_string!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     group.finish();
@@ -280,12 +298,14 @@ pub fn lookup_miss(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_lookup_miss!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup_miss!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup_miss!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_lookup_miss!(group, StdHashMap, "std", SIZE, LEN);
         bench_lookup_miss!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_lookup_miss!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     group.finish();
@@ -324,17 +344,102 @@ pub fn remove(c: &mut Criterion) {
         const LEN: usize = 1;
         bench_remove!(group, StdHashMap, "std", SIZE, LEN);
         bench_remove!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_remove!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     {
         const LEN: usize = 8;
         bench_remove!(group, StdHashMap, "std", SIZE, LEN);
         bench_remove!(group, CbHashMap, "cb", SIZE, LEN);
+        bench_remove!(group, RobinHoodMap, "robin_hood", SIZE, LEN);
     }
 
     group.finish();
 }
 
+type WidthMap<G> = fifth::Map<usize, usize, DefaultHashBuilder, Global, G>;
+
+fn bench_width<G: Group>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    width: &str,
+    hits: &[usize],
+    misses: &[usize],
+) {
+    let filled = || {
+        let mut map = WidthMap::<G>::default();
+        for &i in hits {
+            map.insert(i, i);
+        }
+        map
+    };
+
+    group.bench_function(BenchmarkId::new("insert_grow_random", width), |b| {
+        b.iter_batched_ref(
+            WidthMap::<G>::default,
+            |map| {
+                for &i in black_box(hits) {
+                    black_box(map.insert(i, i));
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function(BenchmarkId::new("lookup", width), |b| {
+        b.iter_batched_ref(
+            filled,
+            |map| {
+                for i in black_box(hits) {
+                    black_box(map.get(i));
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function(BenchmarkId::new("lookup_miss", width), |b| {
+        b.iter_batched_ref(
+            filled,
+            |map| {
+                for i in black_box(misses) {
+                    black_box(map.get(i));
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function(BenchmarkId::new("remove", width), |b| {
+        b.iter_batched_ref(
+            filled,
+            |map| {
+                for i in black_box(hits) {
+                    black_box(map.remove(i));
+                }
+                assert!(map.is_empty());
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+/// Compares `fifth::Map` probing 16 and 32 buckets at a time, and with the runtime-detected
+/// backend.
+///
+/// The 32-wide groups only get a single instruction per operation with AVX2, so run this with
+/// `RUSTFLAGS="-C target-feature=+avx2"`; `DetectedGroup` uses AVX2 either way.
+pub fn group_width(c: &mut Criterion) {
+    let mut seq = RandomKeys::new();
+    let hits: Vec<_> = (&mut seq).take(SIZE).collect();
+    let misses: Vec<_> = (&mut seq).take(SIZE).collect();
+
+    let mut group = c.benchmark_group("group_width");
+    bench_width::<SimdGroup<16>>(&mut group, "16", &hits, &misses);
+    bench_width::<SimdGroup<32>>(&mut group, "32", &hits, &misses);
+    bench_width::<DetectedGroup>(&mut group, "detected", &hits, &misses);
+    group.finish();
+}
+
 criterion_group!(
     benches,
     new,
@@ -346,5 +451,6 @@ criterion_group!(
     lookup_string,
     lookup_miss,
     remove,
+    group_width,
 );
 criterion_main!(benches);
@@ -0,0 +1,375 @@
+//! A concurrent map made of [`fifth::Map`](crate::fifth::Map) shards, each behind its own lock.
+//!
+//! Threads only contend when they touch the same shard, so with enough shards most
+//! operations run in parallel.
+
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::ops::{Deref, DerefMut};
+use std::sync::{MappedRwLockReadGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::fifth::Map;
+use crate::{make_hash, DefaultHashBuilder};
+
+/// Aligns a shard to its own cache lines, so that locking one shard doesn't invalidate
+/// its neighbors in other cores' caches.
+///
+/// 128 bytes covers the adjacent-line prefetcher on x86-64 and the cache line size on
+/// Apple's aarch64 chips.
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+type Shard<K, V, S> = CachePadded<RwLock<Map<K, V, S>>>;
+
+pub struct ShardedMap<K, V, S: BuildHasher = DefaultHashBuilder> {
+    hasher: S,
+    /// Shift that takes a hash to its shard index, `64 - log2(shards.len())`.
+    shift: u32,
+    shards: Box<[Shard<K, V, S>]>,
+}
+
+impl<K, V> ShardedMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn with_shards(n_shards: usize) -> Self {
+        Self::with_shards_and_hasher(n_shards, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher + Clone> ShardedMap<K, V, S> {
+    /// Creates a map with four shards per available core.
+    pub fn with_hasher(hasher: S) -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, usize::from);
+        Self::with_shards_and_hasher(cores * 4, hasher)
+    }
+
+    /// Creates a map with `n_shards` shards, rounded up to a power of two.
+    pub fn with_shards_and_hasher(n_shards: usize, hasher: S) -> Self {
+        let n_shards = n_shards.max(1).next_power_of_two();
+        let shards = (0..n_shards)
+            .map(|_| CachePadded(RwLock::new(Map::with_hasher(hasher.clone()))))
+            .collect();
+
+        Self {
+            hasher,
+            shift: u64::BITS - n_shards.trailing_zeros(),
+            shards,
+        }
+    }
+}
+
+impl<K, V, S: BuildHasher> ShardedMap<K, V, S> {
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn n_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the number of items in the map.
+    ///
+    /// The shards are counted one at a time, so this may be out of date if other threads
+    /// are modifying the map.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| read(shard).is_empty())
+    }
+
+    /// An iterator that read-locks each shard in turn.
+    ///
+    /// Only one shard is locked at a time, as long as the previous guard is dropped
+    /// before advancing.
+    pub fn shards(&self) -> impl Iterator<Item = RwLockReadGuard<'_, Map<K, V, S>>> {
+        self.shards.iter().map(|shard| read(shard))
+    }
+
+    /// An iterator that write-locks each shard in turn.
+    pub fn shards_mut(&self) -> impl Iterator<Item = RwLockWriteGuard<'_, Map<K, V, S>>> {
+        self.shards.iter().map(|shard| write(shard))
+    }
+}
+
+impl<K, V, S: BuildHasher + Clone + Default> Default for ShardedMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> ShardedMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Returns a read guard for the value corresponding to the key.
+    ///
+    /// The key's shard stays read-locked until the guard is dropped.
+    pub fn get<Q>(&self, k: &Q) -> Option<MappedRwLockReadGuard<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let (hash, shard) = self.hash_and_shard(k);
+        RwLockReadGuard::filter_map(read(shard), |map| map.get_hashed(hash, k)).ok()
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let (hash, shard) = self.hash_and_shard(k);
+        read(shard).get_hashed(hash, k).is_some()
+    }
+
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        let (hash, shard) = self.hash_and_shard(&k);
+        write(shard).insert_hashed(hash, k, v)
+    }
+
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let (hash, shard) = self.hash_and_shard(k);
+        write(shard).remove_entry_hashed(hash, k).map(|(_, v)| v)
+    }
+
+    /// Updates the value for the key with `update`, or inserts the value returned by `insert`
+    /// if the key isn't present.
+    ///
+    /// This happens under a single write lock, so no other thread can insert the key in between.
+    pub fn upsert<F, G>(&self, k: K, insert: F, update: G)
+    where
+        F: FnOnce() -> V,
+        G: FnOnce(&mut V),
+    {
+        let (hash, shard) = self.hash_and_shard(&k);
+        write(shard)
+            .entry_hashed(hash, k)
+            .and_modify(update)
+            .or_insert_with(insert);
+    }
+
+    /// Returns the key's hash, and the shard it goes in.
+    ///
+    /// The shard maps have clones of our hasher, so they can use the hash as is instead of
+    /// hashing the key again.
+    fn hash_and_shard<Q: ?Sized + Hash>(&self, k: &Q) -> (u64, &RwLock<Map<K, V, S>>) {
+        // The shard maps use the low bits of the same hash for `h2` and the bucket index,
+        // so pick the shard from the high bits.
+        let hash = make_hash(&self.hasher, k);
+        let index = hash.checked_shr(self.shift).unwrap_or(0) as usize;
+        (hash, &self.shards[index])
+    }
+}
+
+// A panic while a shard is locked (e.g. in a user's `Hash` impl or an `upsert` closure)
+// leaves its map in a valid state, so lock poisoning is ignored.
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::concurrent::ShardedMap;
+
+    #[test]
+    fn single_thread() {
+        let map = ShardedMap::with_shards(5);
+        assert_eq!(map.n_shards(), 8);
+        assert!(map.is_empty());
+
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.insert(7, 70), Some(7));
+
+        assert_eq!(*map.get(&7).unwrap(), 70);
+        assert!(map.get(&1000).is_none());
+        assert!(map.contains_key(&999));
+
+        map.upsert(7, || 0, |v| *v += 1);
+        map.upsert(1000, || 0, |v| *v += 1);
+        assert_eq!(*map.get(&7).unwrap(), 71);
+        assert_eq!(*map.get(&1000).unwrap(), 0);
+
+        for i in 0..=1000 {
+            assert!(map.remove(&i).is_some());
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn hashes_each_key_once() {
+        use std::hash::{BuildHasher, DefaultHasher};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone, Default)]
+        struct CountingHashBuilder(Arc<AtomicUsize>);
+
+        impl BuildHasher for CountingHashBuilder {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> DefaultHasher {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                DefaultHasher::new()
+            }
+        }
+
+        let hasher = CountingHashBuilder::default();
+        let map = ShardedMap::with_shards_and_hasher(4, hasher.clone());
+        map.insert(1, 1);
+        assert_eq!(*map.get(&1).unwrap(), 1);
+        assert!(map.contains_key(&1));
+        map.upsert(1, || 0, |v| *v += 1);
+        assert_eq!(map.remove(&1), Some(2));
+        assert_eq!(hasher.0.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let map: ShardedMap<String, usize> = ShardedMap::new();
+        map.insert(String::from("hi"), 1);
+        assert_eq!(*map.get("hi").unwrap(), 1);
+        assert_eq!(map.remove("hi"), Some(1));
+    }
+
+    #[test]
+    fn shards_split_the_items() {
+        let map = ShardedMap::with_shards(16);
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+
+        let lens: Vec<_> = map.shards().map(|shard| shard.len()).collect();
+        assert_eq!(lens.iter().sum::<usize>(), 10_000);
+        assert!(lens.iter().all(|&len| len > 0));
+
+        let mut keys: Vec<_> = map
+            .shards()
+            .flat_map(|shard| shard.keys().copied().collect::<Vec<_>>())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, (0..10_000).collect::<Vec<_>>());
+
+        for mut shard in map.shards_mut() {
+            shard.values_mut().for_each(|v| *v += 1);
+        }
+        assert_eq!(*map.get(&0).unwrap(), 1);
+
+        // A single shard works too.
+        let map = ShardedMap::with_shards(1);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn concurrent_insert_and_remove() {
+        const THREADS: usize = 16;
+        const PER_THREAD: usize = 2000;
+
+        let map = Arc::new(ShardedMap::new());
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let map = Arc::clone(&map);
+                thread::spawn(move || {
+                    let keys = t * PER_THREAD..(t + 1) * PER_THREAD;
+                    for i in keys.clone() {
+                        assert_eq!(map.insert(i, i.to_string()), None);
+                    }
+                    for i in keys.clone() {
+                        assert_eq!(*map.get(&i).unwrap(), i.to_string());
+                    }
+                    // Remove the odd keys again.
+                    for i in keys.filter(|i| i % 2 == 1) {
+                        assert_eq!(map.remove(&i), Some(i.to_string()));
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(map.len(), THREADS * PER_THREAD / 2);
+        for i in 0..THREADS * PER_THREAD {
+            assert_eq!(map.contains_key(&i), i % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn concurrent_upsert() {
+        const THREADS: usize = 16;
+        const KEYS: usize = 100;
+        const ROUNDS: usize = 100;
+
+        let map = ShardedMap::new();
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for _ in 0..ROUNDS {
+                        for k in 0..KEYS {
+                            map.upsert(k, || 1, |v| *v += 1);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), KEYS);
+        for k in 0..KEYS {
+            assert_eq!(*map.get(&k).unwrap(), THREADS * ROUNDS);
+        }
+    }
+
+    #[test]
+    fn poisoned_shard_still_works() {
+        let map = Arc::new(ShardedMap::with_shards(1));
+        map.insert(1, 1);
+
+        let other = Arc::clone(&map);
+        let result = thread::spawn(move || {
+            other.upsert(1, || 0, |_| panic!("oops"));
+        })
+        .join();
+        assert!(result.is_err());
+
+        assert_eq!(*map.get(&1).unwrap(), 1);
+        map.insert(2, 2);
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,561 @@
+//! A bucketized cuckoo hash map.
+//!
+//! Every key has two candidate groups of `GROUP_SIZE` slots, picked by different parts of its
+//! hash, and lives in one of them unless it's in the stash. So a lookup matches two groups of
+//! metadata with SIMD plus a bounded stash, no matter how full the map is or how the keys
+//! collide.
+//!
+//! When both of a new key's groups are full, the insert searches breadth-first for a short
+//! chain of items that can each move to their other group, ending at an empty slot. If it
+//! can't find one, the map grows instead. But if the map is mostly empty, growing won't help:
+//! too many keys share the same groups, so the new key goes in the stash, which holds at most
+//! `MAX_STASH` items. If the stash is full too, the insert panics.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+use crate::group::{self, Group, TargetGroup};
+use crate::growth::{self, DefaultGrowth, GrowthPolicy};
+use crate::metadata::{self, Metadata};
+use crate::{capacity_to_buckets, fast_rem, fix_capacity, make_hash, mix_hash, DefaultHashBuilder};
+
+/// The number of slots in each of the groups that a key can go in.
+const GROUP_SIZE: usize = TargetGroup::WIDTH;
+
+/// The most items that an insert moves to make room, before giving up and growing.
+const MAX_PATH_LEN: usize = 4;
+
+/// The most groups that an insert looks through for an empty slot, before giving up and growing.
+const MAX_SEARCHED_GROUPS: usize = 128;
+
+/// The most items that can go in the stash.
+const MAX_STASH: usize = 8;
+
+/// A group visited by the breadth-first search for an empty slot.
+#[derive(Clone, Copy)]
+struct SearchNode {
+    group: usize,
+    /// The node whose group we came from, or `None` for the new key's own groups.
+    parent: Option<usize>,
+    /// The slot in the parent's group holding the item that would move to this group.
+    slot: usize,
+    /// The number of items that would move to free up a slot in this group.
+    depth: usize,
+}
+
+pub struct Map<K, V, S: BuildHasher = DefaultHashBuilder> {
+    hasher: S,
+    n_items: usize, // Number of live items
+    /// Safety: we maintain the following invariant:
+    /// `self.storage[i]` is initialized whenever `metadata::is_full(self.metadata[i])`.
+    storage: Box<[MaybeUninit<(K, V)>]>,
+    /// The groups never wrap around, so unlike `fifth`, there's no mirrored tail.
+    metadata: Box<[Metadata]>,
+    /// Items that didn't fit in either of their groups while the map was mostly empty, at most
+    /// `MAX_STASH` of them. `n_items` counts these too.
+    stash: Vec<(K, V)>,
+    _ph: PhantomData<(K, V)>,
+}
+
+impl<K, V> Map<K, V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Creates a map that can hold at least `capacity` items without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_buckets_and_hasher(capacity_to_buckets(capacity), hasher)
+    }
+
+    fn with_buckets_and_hasher(n_buckets: usize, hasher: S) -> Self {
+        // `fix_capacity` returns a power of two that is at least 16, so this is a whole number
+        // of groups.
+        let capacity = fix_capacity(n_buckets);
+
+        Self {
+            hasher,
+            n_items: 0,
+            storage: Box::new_uninit_slice(capacity),
+            metadata: vec![metadata::empty(); capacity].into_boxed_slice(),
+            stash: Vec::new(),
+            _ph: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+}
+
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> Clone for Map<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn clone(&self) -> Self {
+        let mut other = Self::with_buckets_and_hasher(self.n_buckets(), self.hasher.clone());
+        assert_eq!(self.n_buckets(), other.n_buckets());
+
+        for (i, &m) in self.metadata.iter().enumerate() {
+            if metadata::is_full(m) {
+                let (k, v) = unsafe { self.storage[i].assume_init_ref() };
+                other.storage[i].write((k.clone(), v.clone()));
+
+                // Important: Only update the metadata after we successfully clone!
+                // If cloning panics, then updating the metadata before cloning
+                // leads to a read of uninitialized memory when `other` is dropped.
+                other.metadata[i] = m;
+                other.n_items += 1;
+            }
+        }
+        other.stash = self.stash.clone();
+        other.n_items += self.stash.len();
+
+        other
+    }
+}
+
+unsafe impl<#[may_dangle] K, #[may_dangle] V, S> Drop for Map<K, V, S>
+where
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        if core::mem::needs_drop::<(K, V)>() {
+            for (i, &m) in self.metadata.iter().enumerate() {
+                if metadata::is_full(m) {
+                    unsafe { self.storage[i].assume_init_drop() };
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn len(&self) -> usize {
+        self.n_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_items == 0
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.metadata
+            .iter()
+            .zip(self.storage.iter())
+            .filter(|(m, _)| metadata::is_full(**m))
+            .map(|(_, item)| {
+                // SAFETY: `self.storage[i]` is initialized whenever `self.metadata[i]` is full.
+                let (k, v) = unsafe { item.assume_init_ref() };
+                (k, v)
+            })
+            .chain(self.stash.iter().map(|(k, v)| (k, v)))
+    }
+
+    /// The number of buckets, not counting the stash.
+    #[inline]
+    pub(crate) fn n_buckets(&self) -> usize {
+        self.storage.len()
+    }
+
+    #[inline]
+    fn n_groups(&self) -> usize {
+        self.n_buckets() / GROUP_SIZE
+    }
+
+    #[inline]
+    fn load_group(&self, group: usize) -> TargetGroup {
+        TargetGroup::from_slice(&self.metadata[group * GROUP_SIZE..])
+    }
+}
+
+impl<K, V, S> Map<K, V, S>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+{
+    /// Returns the index of the bucket holding `k`, if it's in one of its groups.
+    fn probe_find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.n_buckets() == 0 {
+            return None;
+        }
+
+        let (groups, h2) = self.groups_and_h2(k);
+        for group in groups {
+            let candidates = self.load_group(group).to_candidates(h2);
+            for i in group::MaskIter::forward(candidates) {
+                let index = group * GROUP_SIZE + i;
+                // SAFETY: `to_candidates` only matches full buckets.
+                let (kk, _) = unsafe { self.storage[index].assume_init_ref() };
+                if kk.borrow() == k {
+                    return Some(index);
+                }
+            }
+            // Both groups are the same when the hash picks the same one twice.
+            if groups[0] == groups[1] {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Returns the index in the stash of the item with key `k`, if it's there.
+    fn stash_find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.stash.iter().position(|(kk, _)| kk.borrow() == k)
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some(index) = self.probe_find(k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, v) = unsafe { self.storage[index].assume_init_ref() };
+            return Some(v);
+        }
+        self.stash_find(k).map(|index| &self.stash[index].1)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.probe_find(k).is_some() || self.stash_find(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some(index) = self.probe_find(k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, v) = unsafe { self.storage[index].assume_init_mut() };
+            return Some(v);
+        }
+        self.stash_find(k).map(|index| &mut self.stash[index].1)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(index) = self.probe_find(&k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, vv) = unsafe { self.storage[index].assume_init_mut() };
+            return Some(core::mem::replace(vv, v));
+        }
+        if let Some(index) = self.stash_find(&k) {
+            return Some(core::mem::replace(&mut self.stash[index].1, v));
+        }
+
+        if self.needs_resize() {
+            self.resize();
+        }
+        self.insert_new(k, v);
+        None
+    }
+
+    /// Insert a key that isn't in the map yet, growing the map until there's room for it, or
+    /// stashing it if growing won't help.
+    ///
+    /// Panics if growing won't help and the stash is full.
+    fn insert_new(&mut self, k: K, v: V) {
+        loop {
+            let (groups, h2) = self.groups_and_h2(&k);
+            if let Some(index) = self.make_room(groups) {
+                self.metadata[index] = metadata::from_h2(h2);
+                self.storage[index].write((k, v));
+                self.n_items += 1;
+                return;
+            }
+
+            // Growing only helps if the keys are spread out. If the groups are mostly empty and
+            // there's still no room, too many keys have the same hash.
+            if self.n_items - self.stash.len() < self.n_buckets() / 4 {
+                assert!(
+                    self.stash.len() < MAX_STASH,
+                    "too many keys hash to the same groups"
+                );
+                self.stash.push((k, v));
+                self.n_items += 1;
+                return;
+            }
+            self.resize();
+        }
+    }
+
+    /// Find an empty bucket in one of `groups`, moving other items to their other groups to
+    /// make one if needed. Returns `None` if there's no room.
+    fn make_room(&mut self, groups: [usize; 2]) -> Option<usize> {
+        for group in groups {
+            if let Some(i) = group::find_first(self.load_group(group).to_empties()) {
+                return Some(group * GROUP_SIZE + i);
+            }
+        }
+
+        let (path, mut node, i) = self.search_for_empty(groups)?;
+        let mut hole = path[node].group * GROUP_SIZE + i;
+        // Walk the path back, moving each item into the hole left by the previous move.
+        while let Some(parent) = path[node].parent {
+            let from = path[parent].group * GROUP_SIZE + path[node].slot;
+            self.metadata[hole] = self.metadata[from];
+            self.metadata[from] = metadata::empty();
+            self.storage.swap(hole, from);
+            hole = from;
+            node = parent;
+        }
+        Some(hole)
+    }
+
+    /// Search breadth-first for a group with an empty slot, starting from `groups` (which are
+    /// both full) and following each item to its other group.
+    ///
+    /// Returns the nodes searched, along with the node and slot of the empty bucket. Following
+    /// the parents from that node gives the shortest path of moves to free up a slot in one of
+    /// `groups`.
+    fn search_for_empty(&self, groups: [usize; 2]) -> Option<(Vec<SearchNode>, usize, usize)> {
+        let mut path: Vec<_> = groups
+            .iter()
+            .map(|&group| SearchNode {
+                group,
+                parent: None,
+                slot: 0,
+                depth: 0,
+            })
+            .collect();
+        path.dedup_by_key(|node| node.group);
+
+        let mut next = 0;
+        while next < path.len() {
+            let node = path[next];
+            if let Some(i) = group::find_first(self.load_group(node.group).to_empties()) {
+                return Some((path, next, i));
+            }
+
+            if node.depth < MAX_PATH_LEN {
+                for slot in 0..GROUP_SIZE {
+                    if path.len() == MAX_SEARCHED_GROUPS {
+                        break;
+                    }
+                    // SAFETY: the group has no empty buckets, and there are no tombstones,
+                    // so every bucket is initialized.
+                    let (k, _) =
+                        unsafe { self.storage[node.group * GROUP_SIZE + slot].assume_init_ref() };
+                    let ([first, second], _) = self.groups_and_h2(k);
+                    let other = if first == node.group { second } else { first };
+                    if other != node.group {
+                        path.push(SearchNode {
+                            group: other,
+                            parent: Some(next),
+                            slot,
+                            depth: node.depth + 1,
+                        });
+                    }
+                }
+            }
+            next += 1;
+        }
+        None
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let Some(index) = self.probe_find(k) else {
+            let index = self.stash_find(k)?;
+            self.n_items -= 1;
+            return Some(self.stash.swap_remove(index).1);
+        };
+        let old_bucket = core::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
+        // SAFETY: `probe_find` only returns initialized buckets.
+        let (_, vv) = unsafe { old_bucket.assume_init() };
+        // Lookups never probe past a group, so this can always go back to empty.
+        self.metadata[index] = metadata::empty();
+        self.n_items -= 1;
+        Some(vv)
+    }
+
+    /// Returns the two groups that `k` can go in, and its `h2`.
+    fn groups_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> ([usize; 2], u8) {
+        let hash = mix_hash(make_hash(&self.hasher, k));
+        let h2 = (hash & 0x7F) as u8;
+        // Take the groups from different bits of the hash, so that keys in the same first
+        // group are spread out over the second groups.
+        let first = fast_rem((hash >> 7) as usize, self.n_groups());
+        let second = fast_rem((hash >> 32) as usize, self.n_groups());
+        ([first, second], h2)
+    }
+
+    fn needs_resize(&self) -> bool {
+        // Using a load factor of 7/8.
+        self.n_items >= growth::bucket_capacity::<DefaultGrowth>(self.n_buckets())
+    }
+
+    fn resize(&mut self) {
+        // Calculate the new capacity.
+        let capacity = match self.n_buckets() {
+            0 => DefaultGrowth::MIN_BUCKETS,
+            x => growth::grow::<DefaultGrowth>(x),
+        };
+
+        // Set `self.storage` to a new array.
+        let new_storage = Box::new_uninit_slice(capacity);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
+        let old_buckets = Vec::from(old_storage).into_iter();
+
+        // Here, we need to keep the old metadata, as it's unsafe to blindly access the old storage
+        // array.
+        let new_metadata = vec![metadata::empty(); capacity].into_boxed_slice();
+        let old_metadata = core::mem::replace(&mut self.metadata, new_metadata);
+        let old_stash = core::mem::take(&mut self.stash);
+
+        self.n_items = 0;
+
+        // Move items from `old_storage` to `self.storage`.
+        for (&metadata, bucket) in old_metadata.iter().zip(old_buckets) {
+            if metadata::is_full(metadata) {
+                // SAFETY: we just checked the invariant above.
+                let (k, v) = unsafe { bucket.assume_init() };
+                self.insert_new(k, v);
+            }
+        }
+        for (k, v) in old_stash {
+            self.insert_new(k, v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eighth::Map;
+    crate::generate_tests!(Map, false);
+    crate::generate_non_alloc_tests!(Map);
+
+    #[test]
+    fn items_stay_in_their_groups() {
+        let mut map = Map::new();
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        for i in (0..10_000).step_by(3) {
+            map.remove(&i);
+        }
+
+        for (i, &m) in map.metadata.iter().enumerate() {
+            if crate::metadata::is_full(m) {
+                let (k, _) = unsafe { map.storage[i].assume_init_ref() };
+                let (groups, _) = map.groups_and_h2(k);
+                assert!(groups.contains(&(i / super::GROUP_SIZE)));
+            }
+        }
+    }
+
+    #[test]
+    fn fills_past_one_choice() {
+        // With only two choices per key, filling each group relies on moving items around.
+        let mut map = Map::with_capacity(1 << 12);
+        let buckets = map.n_buckets();
+        let capacity = crate::growth::bucket_capacity::<crate::growth::DefaultGrowth>(buckets);
+        for i in 0..capacity {
+            map.insert(i, i);
+        }
+        assert_eq!(map.n_buckets(), buckets);
+        for i in 0..capacity {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn bad_hash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::ConstantHasher;
+
+        // Every key has the same groups, which holds `GROUP_SIZE` of them, and the rest go in the
+        // stash.
+        let n = super::GROUP_SIZE + super::MAX_STASH;
+        let mut map: Map<usize, usize, BuildHasherDefault<ConstantHasher>> = Map::default();
+        for i in 0..n {
+            map.insert(i, i);
+            assert!(map.stash.len() <= super::MAX_STASH);
+        }
+        assert_eq!(map.stash.len(), super::MAX_STASH);
+        assert_eq!(map.len(), n);
+        assert_eq!(map.iter().count(), n);
+        for i in 0..n {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        for i in (0..n).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.len(), n / 2);
+        assert_eq!(map.clone().iter().count(), n / 2);
+        for i in 0..n {
+            assert_eq!(map.contains_key(&i), i % 2 == 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "too many keys hash to the same groups")]
+    fn bad_hash_overflows_stash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::ConstantHasher;
+
+        let mut map: Map<usize, usize, BuildHasherDefault<ConstantHasher>> = Map::default();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+    }
+
+    #[test]
+    fn identity_hash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::IdentityHasher;
+
+        let mut map: Map<u64, u64, BuildHasherDefault<IdentityHasher>> = Map::default();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        // Mixing the hash spreads the keys out, so none of them need the stash.
+        assert!(map.stash.is_empty());
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+}
@@ -1,30 +1,114 @@
 //! A Swiss Tables-inspired map with metadata.
 //! Uses SSE instructions on the metadata.
 
+use alloc::alloc::{Allocator, Global, Layout};
+use alloc::boxed::Box;
+use core::borrow::Borrow;
+use core::fmt;
 use core::hash::{BuildHasher, Hash};
-use std::intrinsics::{likely, unlikely};
-use std::marker::PhantomData;
-use std::mem::MaybeUninit;
+use core::hint::{likely, unlikely};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::Index;
+use core::ptr::NonNull;
+#[cfg(feature = "std")]
+use std::collections::HashMap as StdHashMap;
 
+use crate::group::{self, BitMask, DefaultGroup, Group};
+use crate::growth::{self, DefaultGrowth, GrowthPolicy};
 use crate::metadata::{self, Metadata};
-use crate::sse::{self, GROUP_SIZE};
-use crate::{fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
+use crate::stats::{self, MapStats};
+
+#[cfg(feature = "rayon")]
+pub mod rayon;
+use crate::{fast_rem, handle_reserve_error, make_hash, DefaultHashBuilder, TryReserveError};
 
 pub enum ProbeResult {
     Empty(usize, u8),
     Full(usize),
 }
 
-pub struct Map<K, V, S: BuildHasher = DefaultHashBuilder> {
+/// The backing storage for the buckets.
+type Storage<K, V, A> = Box<[MaybeUninit<(K, V)>], A>;
+
+pub struct Map<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
     hasher: S,
     n_items: usize,    // Number of live items
     n_occupied: usize, // Number of occupied buckets
     /// Safety: we maintain the following invariant:
     /// `self.storage[i]` is initialized whenever `metadata::is_full(self.metadata[i])`.
-    storage: Box<[MaybeUninit<(K, V)>]>,
-    /// Contains an extra `GROUP_SIZE` elements to avoid wrapping SIMD access
-    metadata: Box<[Metadata]>,
-    _ph: PhantomData<(K, V)>,
+    storage: Storage<K, V, A>,
+    /// Contains an extra `G::WIDTH` elements to avoid wrapping SIMD access
+    metadata: Box<[Metadata], A>,
+    /// How to match groups of metadata, chosen when the map is created.
+    backend: G::Backend,
+    _ph: PhantomData<(K, V, G)>,
+    /// The policy is only used at compile time, so it doesn't need to be `Send` or `Sync`.
+    _policy: PhantomData<fn() -> P>,
+}
+
+/// Allocate uninitialized storage for `capacity` buckets.
+fn try_new_storage<K, V, A: Allocator>(
+    capacity: usize,
+    alloc: A,
+) -> Result<Storage<K, V, A>, TryReserveError> {
+    Box::try_new_uninit_slice_in(capacity, alloc).map_err(|_| alloc_error::<(K, V)>(capacity))
+}
+
+/// Allocate metadata for `capacity` buckets, all set to empty.
+///
+/// Unless `capacity` is zero, this includes the extra mirrored `G::WIDTH` tail.
+fn try_new_metadata<G: Group, A: Allocator>(
+    capacity: usize,
+    alloc: A,
+) -> Result<Box<[Metadata], A>, TryReserveError> {
+    let len = if capacity == 0 {
+        0
+    } else {
+        capacity
+            .checked_add(G::WIDTH)
+            .ok_or(TryReserveError::CapacityOverflow)?
+    };
+    let mut metadata =
+        Box::try_new_uninit_slice_in(len, alloc).map_err(|_| alloc_error::<Metadata>(len))?;
+    metadata.fill(MaybeUninit::new(metadata::empty()));
+    // SAFETY: we just initialized every element.
+    Ok(unsafe { metadata.assume_init() })
+}
+
+fn new_storage<K, V, A: Allocator>(capacity: usize, alloc: A) -> Storage<K, V, A> {
+    try_new_storage(capacity, alloc).unwrap_or_else(|e| handle_reserve_error(e))
+}
+
+fn new_metadata<G: Group, A: Allocator>(capacity: usize, alloc: A) -> Box<[Metadata], A> {
+    try_new_metadata::<G, A>(capacity, alloc).unwrap_or_else(|e| handle_reserve_error(e))
+}
+
+/// Rounds a nonzero number of buckets up to at least one whole group.
+///
+/// The growth policy's minimum number of buckets may be smaller than a group.
+fn whole_groups<G: Group>(n_buckets: usize) -> usize {
+    if n_buckets == 0 {
+        0
+    } else {
+        n_buckets.max(G::WIDTH)
+    }
+}
+
+/// The error to report when allocating an array of `len` `T`s fails.
+fn alloc_error<T>(len: usize) -> TryReserveError {
+    match Layout::array::<T>(len) {
+        Ok(layout) => TryReserveError::AllocError { layout },
+        Err(_) => TryReserveError::CapacityOverflow,
+    }
 }
 
 impl<K, V> Map<K, V> {
@@ -33,42 +117,93 @@ impl<K, V> Map<K, V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
-        let capacity = fix_capacity(capacity);
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
 
-        let storage = Box::new_uninit_slice(capacity);
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
 
-        let metadata = if capacity == 0 {
-            Box::new([])
-        } else {
-            (0..(capacity + GROUP_SIZE))
-                .map(|_| metadata::empty())
-                .collect::<Vec<_>>()
-                .into_boxed_slice()
-        };
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, hasher, Global)
+    }
+}
+
+impl<K, V, A: Allocator + Clone> Map<K, V, DefaultHashBuilder, A> {
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_capacity_in(0, alloc)
+    }
+
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, DefaultHashBuilder::default(), alloc)
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Map<K, V, S, A, G, P> {
+    pub fn with_hasher_in(hasher: S, alloc: A) -> Self {
+        Self::with_capacity_and_hasher_in(0, hasher, alloc)
+    }
+
+    /// Creates a map that can hold at least `capacity` items without resizing.
+    pub fn with_capacity_and_hasher_in(capacity: usize, hasher: S, alloc: A) -> Self {
+        let n_buckets =
+            growth::capacity_to_buckets::<P>(capacity).unwrap_or_else(|e| handle_reserve_error(e));
+        Self::with_buckets_and_hasher_in(n_buckets, hasher, alloc)
+    }
+
+    /// Creates a map with `n_buckets` buckets, rounded up to what the policy and group allow.
+    fn with_buckets_and_hasher_in(n_buckets: usize, hasher: S, alloc: A) -> Self {
+        let capacity = whole_groups::<G>(growth::fix_capacity::<P>(n_buckets));
+
+        let storage = new_storage(capacity, alloc.clone());
+        let metadata = new_metadata::<G, _>(capacity, alloc);
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             n_items: 0,
             n_occupied: 0,
             storage,
             metadata,
+            backend: G::detect_backend(),
             _ph: PhantomData,
+            _policy: PhantomData,
         }
     }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns a reference to the map's [`Allocator`].
+    pub fn allocator(&self) -> &A {
+        Box::allocator(&self.storage)
+    }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S, A, G, P> Default for Map<K, V, S, A, G, P>
+where
+    S: BuildHasher + Default,
+    A: Allocator + Clone + Default,
+    G: Group,
+    P: GrowthPolicy,
+{
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher_in(S::default(), A::default())
     }
 }
 
-unsafe impl<#[may_dangle] K, #[may_dangle] V, S> Drop for Map<K, V, S>
+unsafe impl<#[may_dangle] K, #[may_dangle] V, S, A, G, P> Drop for Map<K, V, S, A, G, P>
 where
     S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
 {
     fn drop(&mut self) {
-        if std::mem::needs_drop::<(K, V)>() {
+        if core::mem::needs_drop::<(K, V)>() {
             for (i, &m) in self.metadata.iter().take(self.n_buckets()).enumerate() {
                 if metadata::is_full(m) {
                     unsafe { self.storage[i].assume_init_drop() };
@@ -78,13 +213,21 @@ where
     }
 }
 
-impl<K, V> Clone for Map<K, V>
+impl<K, V, S, A, G, P> Clone for Map<K, V, S, A, G, P>
 where
-    K: Clone + PartialEq + Eq + Hash,
+    K: Clone,
     V: Clone,
+    S: BuildHasher + Clone,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
 {
     fn clone(&self) -> Self {
-        let mut other = Self::with_capacity(self.n_buckets());
+        let mut other = Self::with_buckets_and_hasher_in(
+            self.n_buckets(),
+            self.hasher.clone(),
+            self.allocator().clone(),
+        );
         assert_eq!(self.n_buckets(), other.n_buckets());
 
         for (i, m) in self.metadata.iter().enumerate().take(self.n_buckets()) {
@@ -104,7 +247,7 @@ where
     }
 }
 
-impl<K, V, S: BuildHasher> Map<K, V, S> {
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Map<K, V, S, A, G, P> {
     pub fn len(&self) -> usize {
         self.n_items
     }
@@ -113,38 +256,232 @@ impl<K, V, S: BuildHasher> Map<K, V, S> {
         self.n_items == 0
     }
 
+    /// Returns the number of items the map can hold without resizing.
+    pub fn capacity(&self) -> usize {
+        // Tombstones use up space until the next resize.
+        growth::bucket_capacity::<P>(self.n_buckets()) - (self.n_occupied - self.n_items)
+    }
+
+    /// Clears the map, removing all key-value pairs. Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        drop(self.drain());
+    }
+
     /// Used for tests
     #[inline]
-    fn n_buckets(&self) -> usize {
+    pub(crate) fn n_buckets(&self) -> usize {
         self.storage.len()
     }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, K, V, G> {
+        Iter {
+            raw: RawIter::new(self.backend, &self.metadata, self.n_buckets(), self.n_items),
+            metadata: &self.metadata,
+            storage: &self.storage,
+        }
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order,
+    /// with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, G> {
+        IterMut {
+            raw: RawIter::new(self.backend, &self.metadata, self.n_buckets(), self.n_items),
+            metadata: &self.metadata,
+            storage: NonNull::from(&mut *self.storage).cast(),
+            _ph: PhantomData,
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, G> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, G> {
+        Values { inner: self.iter() }
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V, G> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    /// Creates a consuming iterator visiting all the keys in arbitrary order.
+    pub fn into_keys(self) -> IntoKeys<K, V, S, A, G, P> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// Creates a consuming iterator visiting all the values in arbitrary order.
+    pub fn into_values(self) -> IntoValues<K, V, S, A, G, P> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// Retains only the elements specified by the predicate.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.extract_if(|k, v| !f(k, v)).for_each(drop);
+    }
+
+    /// Clears the map, returning all key-value pairs as an iterator.
+    /// Keeps the allocated memory for reuse.
+    ///
+    /// If the returned iterator is dropped before being fully consumed,
+    /// it drops the remaining key-value pairs.
+    pub fn drain(&mut self) -> Drain<'_, K, V, S, A, G, P> {
+        Drain {
+            inner: self.take_table().into_iter(),
+            map: self,
+        }
+    }
+
+    /// Move the allocation into a temporary map, leaving `self` empty.
+    ///
+    /// Used while draining, so that leaking the iterator leaves `self` empty instead of
+    /// pointing at items that were already moved out.
+    fn take_table(&mut self) -> Map<K, V, DefaultHashBuilder, A, G, P> {
+        let alloc = self.allocator().clone();
+        Map {
+            hasher: DefaultHashBuilder::default(),
+            n_items: core::mem::replace(&mut self.n_items, 0),
+            n_occupied: core::mem::replace(&mut self.n_occupied, 0),
+            storage: core::mem::replace(&mut self.storage, new_storage(0, alloc.clone())),
+            metadata: core::mem::replace(&mut self.metadata, new_metadata::<G, _>(0, alloc)),
+            backend: self.backend,
+            _ph: PhantomData,
+            _policy: PhantomData,
+        }
+    }
+
+    /// Give back an allocation taken by `take_table`, once all of its items have been moved out.
+    fn restore_table(&mut self, table: &mut Map<K, V, DefaultHashBuilder, A, G, P>) {
+        // Every bucket is now either empty or a tombstone, so reset them.
+        table.metadata.fill(metadata::empty());
+        let alloc = table.allocator().clone();
+        self.storage = core::mem::replace(&mut table.storage, new_storage(0, alloc.clone()));
+        self.metadata = core::mem::replace(&mut table.metadata, new_metadata::<G, _>(0, alloc));
+    }
+
+    /// Creates an iterator which uses a closure to determine if an element should be removed.
+    ///
+    /// If the closure returns `true`, the element is removed from the map and yielded.
+    /// If the iterator is dropped before being fully consumed, the remaining elements are
+    /// retained.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, F, S, A, G, P>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf {
+            raw: RawIter::new(self.backend, &self.metadata, self.n_buckets(), self.n_items),
+            map: self,
+            pred,
+        }
+    }
+
+    /// Loads the group of metadata starting at bucket `index`.
+    #[inline]
+    fn load_group(&self, index: usize) -> G {
+        G::load(self.backend, &self.metadata[index..])
+    }
+
+    fn set_metadata(&mut self, index: usize, value: Metadata) {
+        let index = fast_rem(index, self.n_buckets());
+        let index2 = fast_rem(index.wrapping_sub(G::WIDTH), self.n_buckets()) + G::WIDTH;
+        self.metadata[index] = value;
+        self.metadata[index2] = value;
+    }
+
+    /// Move the item out of bucket `index`, leaving behind an empty bucket or a tombstone.
+    ///
+    /// SAFETY: `self.storage[index]` must be initialized.
+    unsafe fn remove_at(&mut self, index: usize) -> (K, V) {
+        let old_bucket = core::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
+        let kv = old_bucket.assume_init();
+
+        let metadata_value = self.decide_tombstone_or_empty(index);
+        self.set_metadata(index, metadata_value);
+
+        self.n_items -= 1;
+        // Branchless way of decrementing if `is_empty(metadata_value)`.
+        // `metadata::empty()` is 0x80, `metadata::tombstone()` is 0xFE.
+        self.n_occupied -= ((!metadata_value >> 6) & 0b1) as usize;
+        kv
+    }
+
+    /// We can set back to empty unless we're inside a run of `G::WIDTH`
+    /// non-empty buckets.
+    fn decide_tombstone_or_empty(&self, index: usize) -> Metadata {
+        // Degenerate case where n_buckets is G::WIDTH
+        if self.n_buckets() == G::WIDTH {
+            return metadata::empty();
+        }
+
+        let probe_current = self.load_group(index);
+        let next_empty = group::find_first(probe_current.to_empties()).unwrap_or(G::WIDTH);
+
+        let previous = fast_rem(index.wrapping_sub(G::WIDTH), self.n_buckets());
+        let probe_previous = self.load_group(previous);
+        let last_empty = group::find_last(probe_previous.to_empties()).unwrap_or(0);
+
+        // Find the distance between nearest two empty buckets.
+        // If it's less than G::WIDTH, then all groups containing `index` have
+        // at least one empty bucket.
+        if likely((next_empty + G::WIDTH).saturating_sub(last_empty) < G::WIDTH) {
+            metadata::empty()
+        } else {
+            metadata::tombstone()
+        }
+    }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S, A, G, P> Map<K, V, S, A, G, P>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
 {
-    fn probe_find(&self, k: &K) -> ProbeResult {
-        let (mut current, h2) = self.bucket_index_and_h2(k);
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.probe_find_hashed(make_hash(&self.hasher, k), k)
+    }
+
+    /// Like `probe_find`, but with the key's hash already computed.
+    fn probe_find_hashed<Q>(&self, hash: u64, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq,
+    {
+        let (mut current, h2) = self.hash_to_index_and_h2(hash);
 
         for step in 0..self.n_buckets() {
-            current = fast_rem(current + step * GROUP_SIZE, self.n_buckets());
-            let group = sse::Group::from_slice(&self.metadata[current..]);
+            current = fast_rem(current + step * G::WIDTH, self.n_buckets());
+            let group = self.load_group(current);
 
             // First, check full buckets.
-            let candidates = sse::MaskIter::forward(group.to_candidates(h2));
+            let candidates = group::MaskIter::forward(group.to_candidates(h2));
             for i in candidates {
                 let index = fast_rem(current + i, self.n_buckets());
                 // SAFETY: we checked the invariant that `meta.is_value()`.
                 let (kk, _) = unsafe { self.storage.get_unchecked(index).assume_init_ref() };
-                if kk == k {
+                if kk.borrow() == k {
                     return ProbeResult::Full(index);
                 }
             }
 
             // If we've made it to here, our key isn't in this group.
             // Look for the first empty bucket.
-            let empty = sse::find_first(group.to_empties());
+            let empty = group::find_first(group.to_empties());
             if let Some(i) = empty {
                 let index = fast_rem(current + i, self.n_buckets());
                 return ProbeResult::Empty(index, h2);
@@ -160,15 +497,21 @@ where
         }
     }
 
-    fn set_metadata(&mut self, index: usize, value: Metadata) {
-        let index = fast_rem(index, self.n_buckets());
-        let index2 = fast_rem(index.wrapping_sub(GROUP_SIZE), self.n_buckets()) + GROUP_SIZE;
-        self.metadata[index] = value;
-        self.metadata[index2] = value;
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_hashed(make_hash(&self.hasher, k), k)
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
-        match self.probe_find(k) {
+    /// Like `get`, but with the key's hash already computed by the map's hasher.
+    pub(crate) fn get_hashed<Q>(&self, hash: u64, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq,
+    {
+        match self.probe_find_hashed(hash, k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
                 // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
@@ -178,7 +521,52 @@ where
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    /// Returns the key-value pair corresponding to the supplied key.
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Empty(..) => None,
+            ProbeResult::Full(index) => {
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (k, v) = unsafe { self.storage[index].assume_init_ref() };
+                Some((k, v))
+            }
+        }
+    }
+
+    /// Like `get_key_value`, but mutable. Only for the set's `replace`, since changing the key
+    /// in a way that changes its hash or equality would break the map.
+    pub(crate) fn get_key_value_mut<Q>(&mut self, k: &Q) -> Option<(&mut K, &mut V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Empty(..) => None,
+            ProbeResult::Full(index) => {
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (k, v) = unsafe { self.storage[index].assume_init_mut() };
+                Some((k, v))
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
@@ -189,15 +577,63 @@ where
         }
     }
 
+    /// Attempts to get mutable references to `N` values in the map at once.
+    ///
+    /// Returns `None` if any of the keys are missing, or if any two keys are equal.
+    pub fn get_many_mut<Q, const N: usize>(&mut self, ks: [&Q; N]) -> Option<[&mut V; N]>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let mut indices = [0; N];
+        for (index, k) in indices.iter_mut().zip(ks) {
+            match self.probe_find(k) {
+                ProbeResult::Empty(..) => return None,
+                ProbeResult::Full(i) => *index = i,
+            }
+        }
+        for (i, index) in indices.iter().enumerate() {
+            if indices[..i].contains(index) {
+                return None;
+            }
+        }
+
+        let storage = self.storage.as_mut_ptr();
+        // SAFETY: `ProbeResult::Full` implies that each bucket is initialized,
+        // and we checked that the indices are distinct so the references don't alias.
+        Some(indices.map(|index| unsafe { &mut (*storage.add(index)).assume_init_mut().1 }))
+    }
+
     pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.insert_hashed(make_hash(&self.hasher, &k), k, v)
+    }
+
+    /// Like `insert`, but with the key's hash already computed by the map's hasher.
+    pub(crate) fn insert_hashed(&mut self, hash: u64, k: K, v: V) -> Option<V> {
         if unlikely(self.needs_resize()) {
             self.resize();
         }
-        self._insert(k, v)
+        self._insert(hash, k, v)
+    }
+
+    /// Tries to insert a key-value pair into the map, and returns a mutable reference to the
+    /// value in the entry.
+    ///
+    /// If the map already had this key present, nothing is updated, and an error containing the
+    /// occupied entry and the value is returned.
+    pub fn try_insert(
+        &mut self,
+        k: K,
+        v: V,
+    ) -> Result<&mut V, OccupiedError<'_, K, V, S, A, G, P>> {
+        match self.entry(k) {
+            Entry::Occupied(entry) => Err(OccupiedError { entry, value: v }),
+            Entry::Vacant(entry) => Ok(entry.insert(v)),
+        }
     }
 
-    fn _insert(&mut self, k: K, v: V) -> Option<V> {
-        match self.probe_find(&k) {
+    fn _insert(&mut self, hash: u64, k: K, v: V) -> Option<V> {
+        match self.probe_find_hashed(hash, &k) {
             ProbeResult::Empty(index, h2) => {
                 self.set_metadata(index, metadata::from_h2(h2));
                 self.storage[index].write((k, v));
@@ -208,123 +644,1953 @@ where
             ProbeResult::Full(index) => {
                 // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
                 let (_, vv) = unsafe { self.storage[index].assume_init_mut() };
-                Some(std::mem::replace(vv, v))
+                Some(core::mem::replace(vv, v))
             }
         }
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
-        match self.probe_find(k) {
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the key was in the map.
+    pub fn remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.remove_entry_hashed(make_hash(&self.hasher, k), k)
+    }
+
+    /// Like `remove_entry`, but with the key's hash already computed by the map's hasher.
+    pub(crate) fn remove_entry_hashed<Q>(&mut self, hash: u64, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq,
+    {
+        match self.probe_find_hashed(hash, k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
-                let old_bucket = std::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
                 // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
-                let (_, vv) = unsafe { old_bucket.assume_init() };
+                let kv = unsafe { self.remove_at(index) };
+                self.shrink_if_sparse();
+                Some(kv)
+            }
+        }
+    }
 
-                let metadata_value = self.decide_tombstone_or_empty(index);
-                self.set_metadata(index, metadata_value);
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// The key is only hashed and probed once, no matter what is done with the entry.
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S, A, G, P> {
+        self.entry_hashed(make_hash(&self.hasher, &k), k)
+    }
 
-                self.n_items -= 1;
-                // Branchless way of decrementing if `is_empty(metadata_value)`.
-                // `metadata::empty()` is 0x80, `metadata::tombstone()` is 0xFE.
-                self.n_occupied -= ((metadata_value >> 1) & 0b1) as usize;
-                Some(vv)
-            }
+    /// Like `entry`, but with the key's hash already computed by the map's hasher.
+    pub(crate) fn entry_hashed(&mut self, hash: u64, k: K) -> Entry<'_, K, V, S, A, G, P> {
+        // Resize up front so that a vacant slot found by the probe stays valid.
+        if unlikely(self.needs_resize()) {
+            self.resize();
+        }
+        match self.probe_find_hashed(hash, &k) {
+            ProbeResult::Empty(index, h2) => Entry::Vacant(VacantEntry {
+                map: self,
+                key: k,
+                index,
+                h2,
+            }),
+            ProbeResult::Full(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
         }
     }
 
-    /// We can set back to empty unless we're inside a run of `GROUP_SIZE`
-    /// non-empty buckets.
-    fn decide_tombstone_or_empty(&self, index: usize) -> Metadata {
-        // Degenerate case where n_buckets is GROUP_SIZE
-        if self.n_buckets() == GROUP_SIZE {
-            return metadata::empty();
+    /// Reserves capacity for at least `additional` more items to be inserted without resizing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new capacity overflows `usize`, and aborts on allocation failure.
+    pub fn reserve(&mut self, additional: usize) {
+        if let Err(e) = self.try_reserve(additional) {
+            handle_reserve_error(e);
+        }
+    }
+
+    /// Tries to reserve capacity for at least `additional` more items to be inserted without
+    /// resizing. On error, the map is left unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        // Tombstones take up room too, since new items are only placed in empty buckets.
+        let needed = self
+            .n_occupied
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if needed <= growth::bucket_capacity::<P>(self.n_buckets()) {
+            return Ok(());
         }
 
-        let probe_current = sse::Group::from_slice(&self.metadata[index..]);
-        let next_empty = sse::find_first(probe_current.to_empties()).unwrap_or(GROUP_SIZE);
+        // Rehashing gets rid of all of the tombstones, so we only need room for the live items.
+        let new_items = self.n_items + additional;
+        if new_items <= growth::bucket_capacity::<P>(self.n_buckets()) / 2 {
+            self.rehash_in_place();
+            return Ok(());
+        }
+        let capacity =
+            whole_groups::<G>(growth::capacity_to_buckets::<P>(new_items)?).max(self.n_buckets());
+        self.try_resize(capacity)
+    }
 
-        let previous = fast_rem(index.wrapping_sub(GROUP_SIZE), self.n_buckets());
-        let probe_previous = sse::Group::from_slice(&self.metadata[previous..]);
-        let last_empty = sse::find_last(probe_previous.to_empties()).unwrap_or(0);
+    /// Shrinks the capacity of the map as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
 
-        // Find the distance between nearest two empty buckets.
-        // If it's less than GROUP_SIZE, then all groups containing `index` have
-        // at least one empty bucket.
-        if likely((next_empty + GROUP_SIZE).saturating_sub(last_empty) < GROUP_SIZE) {
-            metadata::empty()
-        } else {
-            metadata::tombstone()
+    /// Shrinks the capacity of the map to hold at least `min_capacity` items,
+    /// while keeping room for the items already in the map.
+    ///
+    /// If the current capacity is already smaller than this, this does nothing.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let capacity = growth::capacity_to_buckets::<P>(min_capacity.max(self.n_items))
+            .map(whole_groups::<G>)
+            .unwrap_or_else(|e| handle_reserve_error(e));
+        if capacity < self.n_buckets() {
+            if let Err(e) = self.try_resize(capacity) {
+                handle_reserve_error(e);
+            }
         }
     }
 
-    fn bucket_index_and_h2(&self, k: &K) -> (usize, u8) {
-        let hash = make_hash(&self.hasher, k);
+    /// Shrinks the map to fit its items if the growth policy says it's gotten too empty.
+    #[inline]
+    fn shrink_if_sparse(&mut self) {
+        if unlikely(growth::should_shrink::<P>(self.n_items, self.n_buckets())) {
+            let capacity = growth::capacity_to_buckets::<P>(self.n_items)
+                .map(|n| whole_groups::<G>(n.max(P::MIN_BUCKETS)))
+                .unwrap_or_else(|e| handle_reserve_error(e));
+            if capacity < self.n_buckets() {
+                if let Err(e) = self.try_resize(capacity) {
+                    handle_reserve_error(e);
+                }
+            }
+        }
+    }
+
+    fn bucket_index_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> (usize, u8) {
+        self.hash_to_index_and_h2(make_hash(&self.hasher, k))
+    }
+
+    #[inline]
+    fn hash_to_index_and_h2(&self, hash: u64) -> (usize, u8) {
         let (h1, h2) = (hash >> 7, (hash & 0x7F) as u8);
         let index = fast_rem(h1 as usize, self.n_buckets());
         (index, h2)
     }
 
+    /// Returns statistics about how the items are laid out in the buckets.
+    ///
+    /// This looks at every bucket and hashes every key, so it's slow.
+    pub fn stats(&self) -> MapStats {
+        let n_buckets = self.n_buckets();
+        let metadata = &self.metadata[..n_buckets];
+        let probes = metadata
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| metadata::is_full(m))
+            .map(|(i, _)| {
+                // SAFETY: `storage[i]` is initialized since `metadata[i]` is full.
+                let (k, _) = unsafe { self.storage[i].assume_init_ref() };
+                let (home, _) = self.bucket_index_and_h2(k);
+                stats::group_probe_length(home, i, n_buckets, G::WIDTH)
+            });
+        MapStats::new(metadata, G::WIDTH, probes)
+    }
+
     #[inline]
     fn needs_resize(&self) -> bool {
-        // Using a load factor of 7/8.
         // NOTE: we need to use n_occupied instead of n_items here!
-        self.n_buckets() == 0 || ((self.n_occupied * 8) / self.n_buckets()) > 7
+        self.n_occupied >= growth::bucket_capacity::<P>(self.n_buckets())
     }
 
     #[cold]
     #[inline(never)]
     fn resize(&mut self) {
+        // If most of the occupied buckets are really tombstones, we can make room by clearing
+        // them out instead of growing.
+        if self.n_items < growth::bucket_capacity::<P>(self.n_buckets()) / 2 {
+            self.rehash_in_place();
+            return;
+        }
+
         // Calculate the new capacity.
         let capacity = match self.n_buckets() {
-            0 => 16,
-            x => x * 2,
+            0 => whole_groups::<G>(P::MIN_BUCKETS),
+            x => growth::grow::<P>(x),
         };
+        if let Err(e) = self.try_resize(capacity) {
+            handle_reserve_error(e);
+        }
+    }
 
-        // Set `self.storage` to a new array.
-        let new_storage = Box::new_uninit_slice(capacity);
-        let old_storage = std::mem::replace(&mut self.storage, new_storage);
+    /// Rehashes every item without reallocating, turning all of the tombstones back into
+    /// empty buckets.
+    ///
+    /// This is done automatically instead of growing when the map is mostly tombstones,
+    /// but can be useful to call directly after removing lots of items.
+    pub fn rehash_in_place(&mut self) {
+        let n_buckets = self.n_buckets();
+        if n_buckets == 0 {
+            return;
+        }
+
+        // First, mark all of the full buckets as tombstones and everything else as empty.
+        // From here on, a tombstone means "holds an item that hasn't been rehashed yet".
+        for m in self.metadata[..n_buckets].iter_mut() {
+            *m = if metadata::is_full(*m) {
+                metadata::tombstone()
+            } else {
+                metadata::empty()
+            };
+        }
+        self.metadata.copy_within(..G::WIDTH, n_buckets);
+
+        // If hashing panics, drop the items we haven't gotten to so the map stays consistent.
+        let guard = RehashGuard { map: self };
+        let map = &mut *guard.map;
+
+        for i in 0..n_buckets {
+            if map.metadata[i] != metadata::tombstone() {
+                continue;
+            }
+
+            loop {
+                // SAFETY: tombstones are holding items that haven't been rehashed yet.
+                let (k, _) = unsafe { map.storage[i].assume_init_ref() };
+                let (start, h2) = map.bucket_index_and_h2(k);
+                let new_i = map.find_insert_slot(start);
+
+                // If the item is already in the group that a lookup would find it in,
+                // we can just leave it where it is.
+                let probe_group =
+                    |index: usize| fast_rem(index.wrapping_sub(start), n_buckets) / G::WIDTH;
+                if probe_group(i) == probe_group(new_i) {
+                    map.set_metadata(i, metadata::from_h2(h2));
+                    break;
+                }
+
+                let previous = map.metadata[new_i];
+                map.set_metadata(new_i, metadata::from_h2(h2));
+                if metadata::is_empty(previous) {
+                    // Move the item into the empty bucket.
+                    map.set_metadata(i, metadata::empty());
+                    // SAFETY: we just checked that `i` was initialized above,
+                    // and it's now marked as empty.
+                    let item = unsafe { map.storage[i].assume_init_read() };
+                    map.storage[new_i].write(item);
+                    break;
+                } else {
+                    // Swap with the item that hasn't been rehashed yet,
+                    // then go around again to rehash that one.
+                    map.storage.swap(i, new_i);
+                }
+            }
+        }
+
+        core::mem::forget(guard);
+        self.n_occupied = self.n_items;
+    }
+
+    /// Find the first bucket along the probe sequence starting at `start` that isn't full.
+    fn find_insert_slot(&self, start: usize) -> usize {
+        let mut current = start;
+        for step in 0..self.n_buckets() {
+            current = fast_rem(current + step * G::WIDTH, self.n_buckets());
+            let group = self.load_group(current);
+            if let Some(i) = group::find_first(group.to_empties_or_tombstones()) {
+                return fast_rem(current + i, self.n_buckets());
+            }
+        }
+        unreachable!("backing storage is full, we didn't resize correctly")
+    }
 
-        let new_metadata = (0..(capacity + GROUP_SIZE))
-            .map(|_| metadata::empty())
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
+    /// Move all of the items into a new allocation with `capacity` buckets.
+    ///
+    /// `capacity` must be zero or a power of two that is at least `G::WIDTH`,
+    /// and must be big enough to hold all of the items.
+    fn try_resize(&mut self, capacity: usize) -> Result<(), TryReserveError> {
+        // Allocate everything up front, so that we can bail out without touching `self`.
+        let alloc = self.allocator().clone();
+        let new_storage = try_new_storage(capacity, alloc.clone())?;
+        let new_metadata = try_new_metadata::<G, _>(capacity, alloc)?;
+
+        // Set `self.storage` to a new array.
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
         // Here, we need to keep the old metadata, as it's unsafe to blindly access the old storage
         // array.
-        let old_metadata = std::mem::replace(&mut self.metadata, new_metadata);
+        let old_metadata = core::mem::replace(&mut self.metadata, new_metadata);
 
         self.n_items = 0;
         self.n_occupied = 0;
 
-        // Chunk metadata and storage into `GROUP_SIZE` chunks
-        let metadata_chunks = Vec::from(old_metadata)
-            .into_iter()
-            .array_chunks::<GROUP_SIZE>();
-        let storage_chunks = Vec::from(old_storage)
-            .into_iter()
-            .array_chunks::<GROUP_SIZE>();
-
-        // Zipping `metadata_chunks` and `storage_chunks` ensures that we correctly ignore the
-        // replicated metadata group.
-        for (m_chunk, s_chunk) in metadata_chunks.zip(storage_chunks) {
-            // Get a mask showing the indices with full buckets.
-            let full_mask = sse::Group::from_array(m_chunk).to_fulls();
-            // Re-insert each full bucket.
-            for (is_full, bucket) in full_mask.to_array().into_iter().zip(s_chunk) {
-                if is_full {
-                    // Safety: if `is_full`, then we can assume the `bucket`
-                    // is initialized according to our safety invariant.
-                    let (k, v) = unsafe { bucket.assume_init() };
-                    self._insert(k, v);
-                }
+        // Walk the old buckets a group at a time, stopping before the replicated metadata group.
+        for start in (0..old_storage.len()).step_by(G::WIDTH) {
+            // Get a mask showing the indices with full buckets, and re-insert each of them.
+            let fulls = G::load(self.backend, &old_metadata[start..]).to_fulls();
+            for i in group::MaskIter::forward(fulls) {
+                // Safety: full buckets are initialized according to our safety invariant,
+                // and we only read each one once. The old storage is `MaybeUninit`, so it
+                // doesn't drop anything when it's freed.
+                let (k, v) = unsafe { old_storage[start + i].assume_init_read() };
+                self._insert(make_hash(&self.hasher, &k), k, v);
             }
         }
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::fifth::Map;
-    crate::generate_tests!(Map, true);
-    crate::generate_non_alloc_tests!(Map);
+/// Cleans up after a panic during `rehash_in_place`.
+///
+/// At that point, tombstones hold items that haven't been rehashed yet. We drop them and
+/// set them to empty, which leaves a valid map with only the items that were already rehashed.
+struct RehashGuard<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> {
+    map: &'a mut Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Drop
+    for RehashGuard<'_, K, V, S, A, G, P>
+{
+    fn drop(&mut self) {
+        let map = &mut *self.map;
+        for i in 0..map.n_buckets() {
+            if map.metadata[i] == metadata::tombstone() {
+                map.set_metadata(i, metadata::empty());
+                map.n_items -= 1;
+                // SAFETY: tombstones are holding items that haven't been rehashed yet.
+                unsafe { map.storage[i].assume_init_drop() };
+            }
+        }
+        map.n_occupied = map.n_items;
+    }
+}
+
+/// A view into a single entry in a map, which may either be vacant or occupied.
+///
+/// This is constructed from the [`entry`](Map::entry) method on [`Map`].
+pub enum Entry<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    Occupied(OccupiedEntry<'a, K, V, S, A, G, P>),
+    Vacant(VacantEntry<'a, K, V, S, A, G, P>),
+}
+
+/// A view into an occupied entry in a [`Map`].
+pub struct OccupiedEntry<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    map: &'a mut Map<K, V, S, A, G, P>,
+    /// SAFETY: `map.storage[index]` is initialized.
+    index: usize,
+}
+
+/// A view into a vacant entry in a [`Map`].
+pub struct VacantEntry<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    map: &'a mut Map<K, V, S, A, G, P>,
+    key: K,
+    /// The empty bucket found by `probe_find`, along with the `h2` of `key`.
+    index: usize,
+    h2: u8,
+}
+
+impl<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy>
+    Entry<'a, K, V, S, A, G, P>
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V: Default, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy>
+    Entry<'a, K, V, S, A, G, P>
+{
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy>
+    OccupiedEntry<'a, K, V, S, A, G, P>
+{
+    pub fn key(&self) -> &K {
+        // SAFETY: `self.index` points to an initialized bucket.
+        let (k, _) = unsafe { self.map.storage[self.index].assume_init_ref() };
+        k
+    }
+
+    pub fn get(&self) -> &V {
+        // SAFETY: `self.index` points to an initialized bucket.
+        let (_, v) = unsafe { self.map.storage[self.index].assume_init_ref() };
+        v
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        // SAFETY: `self.index` points to an initialized bucket.
+        let (_, v) = unsafe { self.map.storage[self.index].assume_init_mut() };
+        v
+    }
+
+    /// Converts the entry into a mutable reference to the value that lives as long as the map.
+    pub fn into_mut(self) -> &'a mut V {
+        // SAFETY: `self.index` points to an initialized bucket.
+        let (_, v) = unsafe { self.map.storage[self.index].assume_init_mut() };
+        v
+    }
+
+    /// Sets the value of the entry, returning the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        core::mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<K, V, S, A, G, P> OccupiedEntry<'_, K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        // SAFETY: `self.index` points to an initialized bucket.
+        let kv = unsafe { self.map.remove_at(self.index) };
+        self.map.shrink_if_sparse();
+        kv
+    }
+}
+
+impl<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy>
+    VacantEntry<'a, K, V, S, A, G, P>
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Sets the value of the entry, returning a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let Self {
+            map,
+            key,
+            index,
+            h2,
+        } = self;
+        map.set_metadata(index, metadata::from_h2(h2));
+        map.n_items += 1;
+        map.n_occupied += 1;
+        let (_, v) = map.storage[index].write((key, value));
+        v
+    }
+}
+
+/// The error returned by [`try_insert`](Map::try_insert) when the key already exists.
+///
+/// Contains the occupied entry, and the value that was not inserted.
+pub struct OccupiedError<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    pub entry: OccupiedEntry<'a, K, V, S, A, G, P>,
+    pub value: V,
+}
+
+impl<K, V, S, A, G, P> fmt::Debug for Entry<'_, K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Occupied(entry) => f.debug_tuple("Entry").field(entry).finish(),
+            Entry::Vacant(entry) => f.debug_tuple("Entry").field(entry).finish(),
+        }
+    }
+}
+
+impl<K, V, S, A, G, P> fmt::Debug for OccupiedEntry<'_, K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<K, V, S, A, G, P> fmt::Debug for VacantEntry<'_, K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+impl<K, V, S, A, G, P> fmt::Debug for OccupiedError<'_, K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
+
+impl<K, V, S, A, G, P> fmt::Display for OccupiedError<'_, K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key {:?} already exists with value {:?}",
+            self.value,
+            self.entry.key(),
+            self.entry.get(),
+        )
+    }
+}
+
+/// Walks the indices of the full buckets, one group at a time.
+///
+/// This doesn't borrow the metadata itself so that it can be shared by all of the iterators
+/// below; the metadata must be passed back in on every call to `next`.
+///
+/// This scans with the map's own group `G`. The number of buckets is a power of two that is
+/// at least `G::WIDTH`, so the groups exactly cover the buckets, and the last one ends where
+/// the mirrored tail starts.
+#[derive(Clone)]
+struct RawIter<G: Group> {
+    /// Index of the first bucket in the current group.
+    group: usize,
+    /// Index one past the last bucket, where the mirrored tail starts.
+    end: usize,
+    backend: G::Backend,
+    /// Full buckets in the current group that haven't been yielded yet.
+    fulls: group::MaskIter<G::BitMask, group::Forward>,
+    /// Number of full buckets that haven't been yielded yet.
+    remaining: usize,
+}
+
+impl<G: Group> RawIter<G> {
+    fn new(backend: G::Backend, metadata: &[Metadata], n_buckets: usize, n_items: usize) -> Self {
+        let fulls = if n_items == 0 {
+            BitMask::NONE
+        } else {
+            G::load(backend, metadata).to_fulls()
+        };
+        Self {
+            group: 0,
+            end: n_buckets,
+            backend,
+            fulls: group::MaskIter::forward(fulls),
+            remaining: n_items,
+        }
+    }
+
+    #[inline]
+    fn next(&mut self, metadata: &[Metadata]) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(i) = self.fulls.next() {
+                self.remaining -= 1;
+                return Some(self.group + i);
+            }
+            self.group += G::WIDTH;
+            if self.group >= self.end {
+                // Only reachable if `remaining` was wrong. Never scan the mirrored tail,
+                // since that would yield buckets a second time.
+                self.remaining = 0;
+                return None;
+            }
+            let group = G::load(self.backend, &metadata[self.group..]);
+            self.fulls = group::MaskIter::forward(group.to_fulls());
+        }
+    }
+}
+
+/// An iterator over the entries of a [`Map`].
+pub struct Iter<'a, K, V, G: Group = DefaultGroup> {
+    raw: RawIter<G>,
+    metadata: &'a [Metadata],
+    storage: &'a [MaybeUninit<(K, V)>],
+}
+
+impl<K, V, G: Group> Clone for Iter<'_, K, V, G> {
+    fn clone(&self) -> Self {
+        Self {
+            raw: self.raw.clone(),
+            metadata: self.metadata,
+            storage: self.storage,
+        }
+    }
+}
+
+impl<'a, K, V, G: Group> Iterator for Iter<'a, K, V, G> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.raw.next(self.metadata)?;
+        // SAFETY: `RawIter` only yields indices of full buckets.
+        let (k, v) = unsafe { self.storage[index].assume_init_ref() };
+        Some((k, v))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<K, V, G: Group> ExactSizeIterator for Iter<'_, K, V, G> {}
+impl<K, V, G: Group> FusedIterator for Iter<'_, K, V, G> {}
+
+/// A mutable iterator over the entries of a [`Map`].
+pub struct IterMut<'a, K, V, G: Group = DefaultGroup> {
+    raw: RawIter<G>,
+    metadata: &'a [Metadata],
+    /// We hand out `&'a mut` references to distinct buckets, so we can't hold onto a
+    /// `&'a mut [MaybeUninit<(K, V)>]` here without aliasing them.
+    storage: NonNull<MaybeUninit<(K, V)>>,
+    _ph: PhantomData<&'a mut (K, V)>,
+}
+
+// SAFETY: `IterMut` behaves like a `&mut [(K, V)]`.
+unsafe impl<K: Send, V: Send, G: Group> Send for IterMut<'_, K, V, G> {}
+unsafe impl<K: Sync, V: Sync, G: Group> Sync for IterMut<'_, K, V, G> {}
+
+impl<'a, K, V, G: Group> Iterator for IterMut<'a, K, V, G> {
+    type Item = (&'a K, &'a mut V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.raw.next(self.metadata)?;
+        // SAFETY: `RawIter` only yields indices of full buckets, and never yields the same
+        // index twice, so the returned references don't alias.
+        let (k, v) = unsafe { (*self.storage.as_ptr().add(index)).assume_init_mut() };
+        Some((&*k, v))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<K, V, G: Group> ExactSizeIterator for IterMut<'_, K, V, G> {}
+impl<K, V, G: Group> FusedIterator for IterMut<'_, K, V, G> {}
+
+/// An owning iterator over the entries of a [`Map`].
+pub struct IntoIter<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    raw: RawIter<G>,
+    /// Buckets are marked empty as they are yielded, so that dropping the map
+    /// only drops the items that haven't been yielded yet.
+    map: Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Iterator
+    for IntoIter<K, V, S, A, G, P>
+{
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.raw.next(&self.map.metadata)?;
+        // Only touch the metadata after `RawIter` has already loaded this bucket's group.
+        self.map.set_metadata(index, metadata::empty());
+        self.map.n_items -= 1;
+        // SAFETY: `RawIter` only yields indices of full buckets, and we just marked it as empty
+        // so it won't be read again.
+        Some(unsafe { self.map.storage[index].assume_init_read() })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> ExactSizeIterator
+    for IntoIter<K, V, S, A, G, P>
+{
+}
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> FusedIterator
+    for IntoIter<K, V, S, A, G, P>
+{
+}
+
+/// An iterator over the keys of a [`Map`].
+pub struct Keys<'a, K, V, G: Group = DefaultGroup> {
+    inner: Iter<'a, K, V, G>,
+}
+
+impl<K, V, G: Group> Clone for Keys<'_, K, V, G> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<'a, K, V, G: Group> Iterator for Keys<'a, K, V, G> {
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, G: Group> ExactSizeIterator for Keys<'_, K, V, G> {}
+impl<K, V, G: Group> FusedIterator for Keys<'_, K, V, G> {}
+
+/// An iterator over the values of a [`Map`].
+pub struct Values<'a, K, V, G: Group = DefaultGroup> {
+    inner: Iter<'a, K, V, G>,
+}
+
+impl<K, V, G: Group> Clone for Values<'_, K, V, G> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<'a, K, V, G: Group> Iterator for Values<'a, K, V, G> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, G: Group> ExactSizeIterator for Values<'_, K, V, G> {}
+impl<K, V, G: Group> FusedIterator for Values<'_, K, V, G> {}
+
+/// A mutable iterator over the values of a [`Map`].
+pub struct ValuesMut<'a, K, V, G: Group = DefaultGroup> {
+    inner: IterMut<'a, K, V, G>,
+}
+
+impl<'a, K, V, G: Group> Iterator for ValuesMut<'a, K, V, G> {
+    type Item = &'a mut V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, G: Group> ExactSizeIterator for ValuesMut<'_, K, V, G> {}
+impl<K, V, G: Group> FusedIterator for ValuesMut<'_, K, V, G> {}
+
+/// An owning iterator over the keys of a [`Map`].
+pub struct IntoKeys<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    inner: IntoIter<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Iterator
+    for IntoKeys<K, V, S, A, G, P>
+{
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> ExactSizeIterator
+    for IntoKeys<K, V, S, A, G, P>
+{
+}
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> FusedIterator
+    for IntoKeys<K, V, S, A, G, P>
+{
+}
+
+/// An owning iterator over the values of a [`Map`].
+pub struct IntoValues<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    inner: IntoIter<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Iterator
+    for IntoValues<K, V, S, A, G, P>
+{
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> ExactSizeIterator
+    for IntoValues<K, V, S, A, G, P>
+{
+}
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> FusedIterator
+    for IntoValues<K, V, S, A, G, P>
+{
+}
+
+/// A draining iterator over the entries of a [`Map`].
+pub struct Drain<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    inner: IntoIter<K, V, DefaultHashBuilder, A, G, P>,
+    map: &'a mut Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Iterator
+    for Drain<'_, K, V, S, A, G, P>
+{
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> ExactSizeIterator
+    for Drain<'_, K, V, S, A, G, P>
+{
+}
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> FusedIterator
+    for Drain<'_, K, V, S, A, G, P>
+{
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Drop
+    for Drain<'_, K, V, S, A, G, P>
+{
+    fn drop(&mut self) {
+        // If dropping an item panics, the allocation is freed along with `self.inner`,
+        // and the map is left empty.
+        self.inner.by_ref().for_each(drop);
+        self.map.restore_table(&mut self.inner.map);
+    }
+}
+
+/// An iterator which removes and yields the entries of a [`Map`] matching a predicate.
+pub struct ExtractIf<
+    'a,
+    K,
+    V,
+    F,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    raw: RawIter<G>,
+    map: &'a mut Map<K, V, S, A, G, P>,
+    pred: F,
+}
+
+impl<K, V, F, S, A, G, P> Iterator for ExtractIf<'_, K, V, F, S, A, G, P>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(index) = self.raw.next(&self.map.metadata) {
+            // SAFETY: `RawIter` only yields indices of full buckets.
+            let (k, v) = unsafe { self.map.storage[index].assume_init_mut() };
+            if (self.pred)(k, v) {
+                // Removing only rewrites the metadata for `index` (and its mirror),
+                // so the rest of the group that `RawIter` already loaded is still valid.
+                // If `pred` panics, the map is left with some items removed and no bucket
+                // in an inconsistent state.
+                //
+                // SAFETY: `RawIter` only yields indices of full buckets.
+                return Some(unsafe { self.map.remove_at(index) });
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.raw.remaining))
+    }
+}
+
+impl<K, V, F, S, A, G, P> FusedIterator for ExtractIf<'_, K, V, F, S, A, G, P>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> IntoIterator
+    for Map<K, V, S, A, G, P>
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, S, A, G, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            raw: RawIter::new(self.backend, &self.metadata, self.n_buckets(), self.n_items),
+            map: self,
+        }
+    }
+}
+
+impl<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> IntoIterator
+    for &'a Map<K, V, S, A, G, P>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, G>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> IntoIterator
+    for &'a mut Map<K, V, S, A, G, P>
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V, G>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K, V, S, A, G, P> FromIterator<(K, V)> for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher + Default,
+    A: Allocator + Clone + Default,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = Self::with_hasher_in(S::default(), A::default());
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S, A, G, P> Extend<(K, V)> for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        // If the map isn't empty, some of the keys are probably already present,
+        // so only reserve room for about half of them (same as std).
+        let iter = iter.into_iter();
+        let additional = if self.is_empty() {
+            iter.size_hint().0
+        } else {
+            iter.size_hint().0.div_ceil(2)
+        };
+        self.reserve(additional);
+
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, S, A, G, P> Extend<(&'a K, &'a V)> for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash + Copy,
+    V: Copy,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn extend<T: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+impl<K, Q, V, S, A, G, P> Index<&Q> for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash + Borrow<Q>,
+    Q: ?Sized + Hash + Eq,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    type Output = V;
+
+    /// Returns a reference to the value corresponding to the supplied key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the map.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K, V, S, A, G, P> PartialEq for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash,
+    V: PartialEq,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K, V, S, A, G, P> Eq for Map<K, V, S, A, G, P>
+where
+    K: PartialEq + Eq + Hash,
+    V: Eq,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+}
+
+impl<K, V, S, A, G, P> fmt::Debug for Map<K, V, S, A, G, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for Map<K, V>
+where
+    K: PartialEq + Eq + Hash,
+{
+    fn from(arr: [(K, V); N]) -> Self {
+        Self::from_iter(arr)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> From<StdHashMap<K, V, S>> for Map<K, V, S>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    fn from(map: StdHashMap<K, V, S>) -> Self {
+        let mut other = Self::with_hasher(map.hasher().clone());
+        other.extend(map);
+        other
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S, A, G, P> From<Map<K, V, S, A, G, P>> for StdHashMap<K, V, S>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher + Clone,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn from(map: Map<K, V, S, A, G, P>) -> Self {
+        let mut other = StdHashMap::with_capacity_and_hasher(map.len(), map.hasher().clone());
+        other.extend(map);
+        other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fifth::Map;
+    // Removed buckets are mostly set back to empty, so reinserting doesn't resize.
+    crate::generate_tests!(Map, false);
+    crate::generate_non_alloc_tests!(Map);
+    crate::generate_reserve_tests!(Map);
+    crate::generate_stats_tests!(Map);
+
+    /// Runs the shared tests against a map that probes with `$group` and grows with `$policy`.
+    ///
+    /// `new` and friends are only defined for the default group and policy, so the tests get
+    /// them from these traits instead. For the defaults themselves, the traits go unused.
+    macro_rules! group_tests {
+        ($name:ident, $group:ty) => {
+            group_tests!($name, $group, crate::growth::DefaultGrowth);
+        };
+        ($name:ident, $group:ty, $policy:ty) => {
+            mod $name {
+                use std::alloc::Global;
+                use std::hash::BuildHasher;
+
+                use crate::DefaultHashBuilder;
+
+                type Map<K, V, S = DefaultHashBuilder> =
+                    crate::fifth::Map<K, V, S, Global, $group, $policy>;
+
+                #[allow(dead_code)]
+                trait New {
+                    fn new() -> Self;
+                    fn with_capacity(capacity: usize) -> Self;
+                }
+
+                impl<K, V> New for Map<K, V> {
+                    fn new() -> Self {
+                        Self::default()
+                    }
+
+                    fn with_capacity(capacity: usize) -> Self {
+                        Self::with_capacity_and_hasher_in(capacity, Default::default(), Global)
+                    }
+                }
+
+                #[allow(dead_code)]
+                trait WithHasher<S> {
+                    fn with_hasher(hasher: S) -> Self;
+                    fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self;
+                }
+
+                impl<K, V, S: BuildHasher> WithHasher<S> for Map<K, V, S> {
+                    fn with_hasher(hasher: S) -> Self {
+                        Self::with_hasher_in(hasher, Global)
+                    }
+
+                    fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+                        Self::with_capacity_and_hasher_in(capacity, hasher, Global)
+                    }
+                }
+
+                crate::generate_tests!(Map, false);
+                crate::generate_non_alloc_tests!(Map);
+                crate::generate_reserve_tests!(Map);
+                crate::generate_stats_tests!(Map);
+            }
+        };
+    }
+
+    group_tests!(simd_16, crate::group::SimdGroup<16>);
+    group_tests!(simd_32, crate::group::SimdGroup<32>);
+    group_tests!(swar, crate::group::SwarGroup);
+    group_tests!(detected, crate::group::DetectedGroup);
+    group_tests!(dense, crate::group::DefaultGroup, crate::testing::Dense);
+    group_tests!(
+        quadrupling,
+        crate::group::DefaultGroup,
+        crate::testing::Quadrupling
+    );
+    // Tables of 8 buckets, which is less than `DefaultGroup::WIDTH` unless that's SWAR too.
+    group_tests!(
+        swar_quadrupling,
+        crate::group::SwarGroup,
+        crate::testing::Quadrupling
+    );
+
+    use crate::fifth::Entry;
+
+    #[test]
+    fn entry_or_insert() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            *map.entry(i % 100).or_insert(0) += 1;
+        }
+
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&10));
+        }
+    }
+
+    #[test]
+    fn entry_or_insert_with() {
+        let mut map: Map<usize, String> = Map::new();
+
+        for i in 0..1000 {
+            map.entry(i).or_insert_with(|| i.to_string());
+            map.entry(i).or_insert_with_key(|_| unreachable!());
+        }
+        assert_eq!(map.len(), 1000);
+
+        for i in 0..1000 {
+            assert_eq!(map.entry(i).or_default(), &i.to_string());
+        }
+    }
+
+    #[test]
+    fn entry_and_modify() {
+        let mut map = Map::new();
+
+        for i in 0..100 {
+            map.entry(i % 10).and_modify(|v| *v *= 2).or_insert(1);
+        }
+
+        assert_eq!(map.len(), 10);
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&(1 << 9)));
+        }
+    }
+
+    #[test]
+    fn occupied_entry() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        for i in 0..1000 {
+            match map.entry(i) {
+                Entry::Vacant(_) => panic!("expected an occupied entry"),
+                Entry::Occupied(mut entry) => {
+                    assert_eq!(entry.key(), &i);
+                    assert_eq!(entry.get(), &i);
+                    *entry.get_mut() += 1;
+                    assert_eq!(entry.insert(0), i + 1);
+
+                    if i % 2 == 0 {
+                        assert_eq!(entry.remove_entry(), (i, 0));
+                    }
+                }
+            }
+        }
+
+        assert_eq!(map.len(), 500);
+        for i in 0..1000 {
+            let expected = if i % 2 == 0 { None } else { Some(&0) };
+            assert_eq!(map.get(&i), expected);
+        }
+    }
+
+    #[test]
+    fn vacant_entry() {
+        let mut map: Map<String, usize> = Map::new();
+
+        for i in 0..1000 {
+            match map.entry(i.to_string()) {
+                Entry::Occupied(_) => panic!("expected a vacant entry"),
+                Entry::Vacant(entry) => {
+                    assert_eq!(entry.key(), &i.to_string());
+                    *entry.insert(i) += 1;
+                }
+            }
+        }
+
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i.to_string()), Some(&(i + 1)));
+        }
+    }
+
+    #[test]
+    fn iter() {
+        let mut map = Map::new();
+        assert_eq!(map.iter().next(), None);
+
+        for i in 0..1000 {
+            map.insert(i, i * 2);
+        }
+
+        let mut iter = map.iter();
+        assert_eq!(iter.len(), 1000);
+        iter.next();
+        assert_eq!(iter.size_hint(), (999, Some(999)));
+
+        let mut items = map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+        let mut keys = map.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, (0..1000).collect::<Vec<_>>());
+
+        let mut values = map.values().copied().collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_skips_removed() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).filter(|i| i % 3 != 0) {
+            map.remove(&i);
+        }
+
+        let mut keys = map.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, (0..1000).filter(|i| i % 3 == 0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        for (k, v) in &mut map {
+            *v += k;
+        }
+        for v in map.values_mut() {
+            *v += 1;
+        }
+
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&(2 * i + 1)));
+        }
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i.to_string(), i.to_string());
+        }
+
+        let mut items = map.clone().into_iter().collect::<Vec<_>>();
+        assert_eq!(items.len(), 1000);
+        items.sort();
+        assert!(items.iter().all(|(k, v)| k == v));
+
+        let mut keys = map.clone().into_keys().collect::<Vec<_>>();
+        let mut values = map.clone().into_values().collect::<Vec<_>>();
+        keys.sort();
+        values.sort();
+        assert_eq!(keys, values);
+
+        // Dropping a partially consumed iterator drops the remaining items exactly once.
+        let mut into_iter = map.into_iter();
+        into_iter.by_ref().take(500).for_each(drop);
+        assert_eq!(into_iter.len(), 500);
+    }
+
+    #[test]
+    fn retain() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        map.retain(|&k, v| {
+            *v += 1;
+            k % 2 == 0
+        });
+
+        assert_eq!(map.len(), 500);
+        for i in 0..1000 {
+            let expected = if i % 2 == 0 { Some(&(i + 1)) } else { None };
+            assert_eq!(map.get(&i), expected);
+        }
+
+        // The map is still usable after removing lots of items.
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn drain() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i.to_string(), i);
+        }
+
+        let buckets = map.n_buckets();
+        let mut drained = map.drain().collect::<Vec<_>>();
+        drained.sort_by_key(|(_, v)| *v);
+
+        assert_eq!(drained.len(), 1000);
+        assert!(drained.iter().enumerate().all(|(i, (_, v))| i == *v));
+        assert!(map.is_empty());
+        assert_eq!(map.n_buckets(), buckets);
+
+        for i in 0..1000 {
+            assert_eq!(map.get(&i.to_string()), None);
+            map.insert(i.to_string(), i);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.n_buckets(), buckets);
+
+        // Dropping the `Drain` early still clears the map.
+        map.drain().take(10).for_each(drop);
+        assert!(map.is_empty());
+        assert_eq!(map.n_buckets(), buckets);
+
+        // Leaking the `Drain` leaves the map empty.
+        std::mem::forget(map.drain());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        let mut extracted = map.extract_if(|k, _| k % 4 == 0).collect::<Vec<_>>();
+        extracted.sort();
+        assert_eq!(
+            extracted,
+            (0..1000).step_by(4).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+        assert_eq!(map.len(), 750);
+
+        // Only the items that are yielded get removed.
+        let taken = map.extract_if(|_, _| true).take(10).count();
+        assert_eq!(taken, 10);
+        assert_eq!(map.len(), 740);
+    }
+
+    #[test]
+    fn extract_if_panic() {
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        #[derive(Clone)]
+        struct Counted(Rc<Cell<usize>>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, Counted(drops.clone()));
+        }
+
+        let mut seen = 0;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            map.retain(|_, _| {
+                seen += 1;
+                if seen == 500 {
+                    panic!("predicate panicked");
+                }
+                false
+            })
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(drops.get(), 499);
+        assert_eq!(map.len(), 501);
+        assert_eq!(map.iter().count(), 501);
+
+        drop(map);
+        assert_eq!(drops.get(), 1000);
+    }
+
+    #[test]
+    fn custom_allocator() {
+        use crate::testing::CountingAllocator;
+
+        let alloc = CountingAllocator::default();
+        let mut map = Map::new_in(alloc.clone());
+        assert_eq!(alloc.0.get(), 0);
+
+        for i in 0..1000 {
+            map.insert(i, i.to_string());
+        }
+        let allocated = alloc.0.get();
+        assert!(allocated > 0);
+
+        let other = map.clone();
+        assert_eq!(alloc.0.get(), 2 * allocated);
+        drop(other);
+
+        // Draining keeps the allocation around.
+        assert_eq!(map.drain().count(), 1000);
+        assert_eq!(alloc.0.get(), allocated);
+
+        map.insert(0, 0.to_string());
+        assert_eq!(map.into_iter().count(), 1);
+        assert_eq!(alloc.0.get(), 0);
+    }
+
+    #[test]
+    fn rehash_in_place() {
+        // Room for 896 items is exactly 1024 buckets.
+        let mut map = Map::with_capacity(896);
+
+        // Fill up the map, then remove most items to leave behind lots of tombstones.
+        for i in 0..890 {
+            map.insert(i, i.to_string());
+        }
+        for i in (0..890).filter(|i| i % 10 != 0) {
+            map.remove(&i);
+        }
+        assert_eq!(map.len(), 89);
+        assert!(map.n_occupied > 89);
+
+        map.rehash_in_place();
+        assert_eq!(map.n_occupied, 89);
+        assert_eq!(map.n_buckets(), 1024);
+        assert!(map
+            .metadata
+            .iter()
+            .all(|&m| m != crate::metadata::tombstone()));
+
+        for i in 0..890 {
+            let expected = (i % 10 == 0).then(|| i.to_string());
+            assert_eq!(map.get(&i), expected.as_ref());
+        }
+        assert_eq!(map.iter().count(), 89);
+    }
+
+    #[test]
+    fn churn_doesnt_grow() {
+        // Room for 896 items is exactly 1024 buckets.
+        let mut map = Map::with_capacity(896);
+
+        for i in 0..890 {
+            map.insert(i, i);
+        }
+        for i in (0..890).filter(|i| i % 10 != 0) {
+            map.remove(&i);
+        }
+
+        // Without rehashing in place, the tombstones would make this grow the map.
+        for i in 0..100_000 {
+            map.insert(1000 + i, i);
+            map.remove(&(1000 + i));
+        }
+        assert_eq!(map.len(), 89);
+        assert_eq!(map.n_buckets(), 1024);
+
+        for i in 1000..1400 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 489);
+        assert_eq!(map.n_buckets(), 1024);
+    }
+
+    #[test]
+    fn rehash_in_place_panic() {
+        use std::cell::Cell;
+        use std::hash::{Hash, Hasher};
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        thread_local! {
+            static HASHES_LEFT: Cell<usize> = const { Cell::new(usize::MAX) };
+        }
+
+        #[derive(PartialEq, Eq)]
+        struct Bomb(usize);
+
+        impl Hash for Bomb {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                let left = HASHES_LEFT.get();
+                if left == 0 {
+                    panic!("bomb!!!");
+                }
+                HASHES_LEFT.set(left - 1);
+                self.0.hash(state);
+            }
+        }
+
+        struct Counted(Rc<Cell<usize>>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        let mut map = Map::with_capacity(1024);
+        for i in 0..500 {
+            map.insert(Bomb(i), Counted(drops.clone()));
+        }
+
+        HASHES_LEFT.set(200);
+        let result = catch_unwind(AssertUnwindSafe(|| map.rehash_in_place()));
+        assert!(result.is_err());
+        HASHES_LEFT.set(usize::MAX);
+
+        // Whatever survived is still consistent.
+        let survivors = map.len();
+        assert!(survivors <= 200);
+        assert_eq!(drops.get(), 500 - survivors);
+        assert_eq!(map.iter().count(), survivors);
+        for (k, _) in map.iter() {
+            assert!(map.contains_key(k));
+        }
+
+        drop(map);
+        assert_eq!(drops.get(), 500);
+    }
+
+    #[test]
+    fn get_key_value_and_remove_entry() {
+        let mut map: Map<String, usize> = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i.to_string(), i);
+        }
+
+        for i in 0..1000 {
+            let k = i.to_string();
+            assert_eq!(map.get_key_value(k.as_str()), Some((&k, &i)));
+            assert_eq!(map.remove_entry(k.as_str()), Some((k.clone(), i)));
+            assert_eq!(map.get_key_value(k.as_str()), None);
+            assert_eq!(map.remove_entry(k.as_str()), None);
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn clear_and_capacity() {
+        let mut map = Map::new();
+        assert_eq!(map.capacity(), 0);
+
+        for i in 0..1000 {
+            map.insert(i, i.to_string());
+        }
+        let capacity = map.capacity();
+        assert!(capacity >= 1000);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.get(&0), None);
+
+        map.reserve(capacity);
+        let capacity = map.capacity();
+        for i in 0..capacity {
+            map.insert(i, i.to_string());
+        }
+        assert_eq!(map.capacity(), capacity);
+    }
+
+    #[test]
+    fn try_insert() {
+        let mut map = Map::new();
+
+        assert_eq!(map.try_insert(1, "a").ok(), Some(&mut "a"));
+        let err = map.try_insert(1, "b").unwrap_err();
+        assert_eq!(err.entry.key(), &1);
+        assert_eq!(err.entry.get(), &"a");
+        assert_eq!(err.value, "b");
+        assert_eq!(
+            err.to_string(),
+            r#"failed to insert "b", key 1 already exists with value "a""#
+        );
+        assert_eq!(map[&1], "a");
+    }
+
+    #[test]
+    fn get_many_mut() {
+        let mut map = Map::new();
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        let [a, b, c] = map.get_many_mut([&1, &10, &100]).unwrap();
+        std::mem::swap(a, b);
+        *c += 1;
+        assert_eq!(map[&1], 10);
+        assert_eq!(map[&10], 1);
+        assert_eq!(map[&100], 101);
+
+        assert!(map.get_many_mut([&1, &2000]).is_none());
+        assert!(map.get_many_mut([&1, &2, &1]).is_none());
+        assert!(map.get_many_mut::<usize, 0>([]).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry found for key")]
+    fn index_missing() {
+        let map: Map<usize, usize> = Map::new();
+        let _ = map[&0];
+    }
+
+    #[test]
+    fn conversions() {
+        let map = Map::from([(1, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&1], "a");
+
+        let mut collected: Map<usize, &str> = (1..=3).zip(["a", "b", "c"]).collect();
+        assert_eq!(map, collected);
+
+        collected.extend([(4, "d"), (1, "z")]);
+        collected.extend(map.iter());
+        assert_ne!(map, collected);
+        assert_eq!(collected.len(), 4);
+        assert_eq!(collected[&1], "a");
+
+        #[cfg(feature = "std")]
+        {
+            use crate::DefaultHashBuilder;
+            use std::collections::HashMap;
+
+            let std_map: HashMap<usize, &str, DefaultHashBuilder> = collected.clone().into();
+            assert_eq!(std_map.len(), 4);
+            let round_trip = Map::from(std_map);
+            assert_eq!(collected, round_trip);
+        }
+
+        let empty: Map<usize, usize> = Map::new();
+        assert_eq!(format!("{:?}", empty), "{}");
+        assert_eq!(format!("{:?}", Map::from([(1, 2)])), "{1: 2}");
+    }
+
+    #[test]
+    fn growth_policy() {
+        use std::alloc::Global;
+
+        use crate::group::SimdGroup;
+        use crate::testing::{Dense, Shrinking};
+        use crate::DefaultHashBuilder;
+
+        // With a load factor of 15/16, 16 buckets hold 15 items instead of 14.
+        let mut dense: Map<usize, usize, DefaultHashBuilder, Global, SimdGroup<16>, Dense> =
+            Map::with_capacity_and_hasher_in(15, DefaultHashBuilder::default(), Global);
+        assert_eq!(dense.capacity(), 15);
+        for i in 0..15 {
+            dense.insert(i, i);
+        }
+        assert_eq!(dense.n_buckets(), 16);
+
+        let mut shrinking: Map<usize, usize, DefaultHashBuilder, Global, SimdGroup<16>, Shrinking> =
+            Map::default();
+        for i in 0..1000 {
+            shrinking.insert(i, i);
+        }
+        let buckets = shrinking.n_buckets();
+        for i in 0..900 {
+            shrinking.remove(&i);
+        }
+        assert!(shrinking.n_buckets() < buckets);
+        for i in 0..1000 {
+            assert_eq!(shrinking.get(&i), (i >= 900).then_some(&i));
+        }
+
+        // It never shrinks below the minimum, so that removing the last item doesn't
+        // give back the allocation.
+        for i in 900..1000 {
+            shrinking.remove(&i);
+        }
+        assert!(shrinking.is_empty());
+        assert_eq!(shrinking.n_buckets(), 16);
+
+        // Removing through an entry shrinks it too.
+        for i in 0..1000 {
+            shrinking.insert(i, i);
+        }
+        let buckets = shrinking.n_buckets();
+        for i in 0..900 {
+            match shrinking.entry(i) {
+                Entry::Occupied(entry) => assert_eq!(entry.remove(), i),
+                Entry::Vacant(_) => panic!("expected an occupied entry"),
+            }
+        }
+        assert!(shrinking.n_buckets() < buckets);
+        for i in 0..1000 {
+            assert_eq!(shrinking.get(&i), (i >= 900).then_some(&i));
+        }
+    }
 }
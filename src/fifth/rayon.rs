@@ -0,0 +1,694 @@
+//! Parallel iterators for [`Map`], behind the `rayon` feature.
+//!
+//! The buckets are split into ranges of whole metadata groups, so each worker scans its own
+//! slices of `metadata` and `storage` with the same SIMD loads as the sequential iterators.
+
+use core::hash::{BuildHasher, Hash};
+use std::alloc::{Allocator, Global};
+use std::collections::LinkedList;
+use std::mem::MaybeUninit;
+
+use ::rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use ::rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
+
+use super::Map;
+use crate::group::{self, BitMask, DefaultGroup, Group};
+use crate::growth::{DefaultGrowth, GrowthPolicy};
+use crate::metadata::{self, Metadata};
+use crate::DefaultHashBuilder;
+
+/// Walks the indices of the full buckets in a range of groups.
+///
+/// Unlike `RawIter`, this doesn't know how many items are left, so it stops at the end of
+/// the range instead. The range never includes the mirrored tail.
+///
+/// Like `RawIter`, this scans with the map's own group `G`.
+struct RawGroups<G: Group> {
+    /// Index of the first bucket in the current group.
+    group: usize,
+    /// Number of buckets in the range, a multiple of the group width.
+    end: usize,
+    backend: G::Backend,
+    /// Full buckets in the current group that haven't been yielded yet.
+    fulls: group::MaskIter<G::BitMask, group::Forward>,
+}
+
+impl<G: Group> RawGroups<G> {
+    fn new(backend: G::Backend, metadata: &[Metadata]) -> Self {
+        let fulls = if metadata.is_empty() {
+            BitMask::NONE
+        } else {
+            G::load(backend, metadata).to_fulls()
+        };
+        Self {
+            group: 0,
+            end: metadata.len(),
+            backend,
+            fulls: group::MaskIter::forward(fulls),
+        }
+    }
+
+    #[inline]
+    fn next(&mut self, metadata: &[Metadata]) -> Option<usize> {
+        loop {
+            if let Some(i) = self.fulls.next() {
+                return Some(self.group + i);
+            }
+            if self.group + G::WIDTH >= self.end {
+                return None;
+            }
+            self.group += G::WIDTH;
+            let group = G::load(self.backend, &metadata[self.group..]);
+            self.fulls = group::MaskIter::forward(group.to_fulls());
+        }
+    }
+}
+
+/// Where to split a range of `len` buckets in half, on a boundary between groups of `G`.
+///
+/// Returns `None` if the range is a single group.
+fn split_point<G: Group>(len: usize) -> Option<usize> {
+    let groups = len / G::WIDTH;
+    (groups > 1).then_some(groups / 2 * G::WIDTH)
+}
+
+/// A parallel iterator over the entries of a [`Map`].
+pub struct ParIter<'a, K, V, G: Group = DefaultGroup> {
+    metadata: &'a [Metadata],
+    storage: &'a [MaybeUninit<(K, V)>],
+    backend: G::Backend,
+}
+
+impl<K, V, G: Group> Clone for ParIter<'_, K, V, G> {
+    fn clone(&self) -> Self {
+        Self {
+            metadata: self.metadata,
+            storage: self.storage,
+            backend: self.backend,
+        }
+    }
+}
+
+impl<'a, K: Sync, V: Sync, G: Group> ParallelIterator for ParIter<'a, K, V, G> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let producer = IterProducer::<_, _, G> {
+            metadata: self.metadata,
+            storage: self.storage,
+            backend: self.backend,
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+struct IterProducer<'a, K, V, G: Group> {
+    metadata: &'a [Metadata],
+    storage: &'a [MaybeUninit<(K, V)>],
+    backend: G::Backend,
+}
+
+impl<'a, K: Sync, V: Sync, G: Group> UnindexedProducer for IterProducer<'a, K, V, G> {
+    type Item = (&'a K, &'a V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let Some(mid) = split_point::<G>(self.storage.len()) else {
+            return (self, None);
+        };
+        let (metadata, other_metadata) = self.metadata.split_at(mid);
+        let (storage, other_storage) = self.storage.split_at(mid);
+        (
+            Self {
+                metadata,
+                storage,
+                backend: self.backend,
+            },
+            Some(Self {
+                metadata: other_metadata,
+                storage: other_storage,
+                backend: self.backend,
+            }),
+        )
+    }
+
+    fn fold_with<F>(self, folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut raw = RawGroups::<G>::new(self.backend, self.metadata);
+        folder.consume_iter(std::iter::from_fn(|| {
+            let index = raw.next(self.metadata)?;
+            // SAFETY: `RawGroups` only yields indices of full buckets.
+            let (k, v) = unsafe { self.storage[index].assume_init_ref() };
+            Some((k, v))
+        }))
+    }
+}
+
+/// A parallel iterator over the entries of a [`Map`], with mutable references to the values.
+pub struct ParIterMut<'a, K, V, G: Group = DefaultGroup> {
+    metadata: &'a [Metadata],
+    storage: &'a mut [MaybeUninit<(K, V)>],
+    backend: G::Backend,
+}
+
+impl<'a, K: Sync + Send, V: Send, G: Group> ParallelIterator for ParIterMut<'a, K, V, G> {
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let producer = IterMutProducer::<_, _, G> {
+            metadata: self.metadata,
+            storage: self.storage,
+            backend: self.backend,
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+struct IterMutProducer<'a, K, V, G: Group> {
+    metadata: &'a [Metadata],
+    storage: &'a mut [MaybeUninit<(K, V)>],
+    backend: G::Backend,
+}
+
+impl<'a, K: Sync + Send, V: Send, G: Group> UnindexedProducer for IterMutProducer<'a, K, V, G> {
+    type Item = (&'a K, &'a mut V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let Some(mid) = split_point::<G>(self.storage.len()) else {
+            return (self, None);
+        };
+        let (metadata, other_metadata) = self.metadata.split_at(mid);
+        let (storage, other_storage) = self.storage.split_at_mut(mid);
+        (
+            Self {
+                metadata,
+                storage,
+                backend: self.backend,
+            },
+            Some(Self {
+                metadata: other_metadata,
+                storage: other_storage,
+                backend: self.backend,
+            }),
+        )
+    }
+
+    fn fold_with<F>(self, folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut raw = RawGroups::<G>::new(self.backend, self.metadata);
+        let storage = self.storage.as_mut_ptr();
+        folder.consume_iter(std::iter::from_fn(|| {
+            let index = raw.next(self.metadata)?;
+            // SAFETY: `RawGroups` only yields indices of full buckets, and never yields the same
+            // index twice, so the returned references don't alias.
+            let (k, v) = unsafe { (*storage.add(index)).assume_init_mut() };
+            Some((&*k, v))
+        }))
+    }
+}
+
+/// A parallel iterator over the keys of a [`Map`].
+pub struct ParKeys<'a, K, V, G: Group = DefaultGroup> {
+    inner: ParIter<'a, K, V, G>,
+}
+
+impl<'a, K: Sync, V: Sync, G: Group> ParallelIterator for ParKeys<'a, K, V, G> {
+    type Item = &'a K;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.inner.map(|(k, _)| k).drive_unindexed(consumer)
+    }
+}
+
+/// A parallel iterator over the values of a [`Map`].
+pub struct ParValues<'a, K, V, G: Group = DefaultGroup> {
+    inner: ParIter<'a, K, V, G>,
+}
+
+impl<'a, K: Sync, V: Sync, G: Group> ParallelIterator for ParValues<'a, K, V, G> {
+    type Item = &'a V;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.inner.map(|(_, v)| v).drive_unindexed(consumer)
+    }
+}
+
+/// A parallel draining iterator over the entries of a [`Map`].
+///
+/// Any entries that aren't consumed are dropped, and the map is left empty.
+pub struct ParDrain<
+    'a,
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    map: &'a mut Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S, A, G, P> ParallelIterator for ParDrain<'_, K, V, S, A, G, P>
+where
+    K: Send,
+    V: Send,
+    S: BuildHasher + Send,
+    A: Allocator + Clone + Send,
+    G: Group,
+    P: GrowthPolicy,
+{
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let mut guard = DrainGuard {
+            table: self.map.take_table(),
+            map: self.map,
+        };
+        let n_buckets = guard.table.n_buckets();
+        let producer = DrainProducer::<_, _, G> {
+            metadata: &mut guard.table.metadata[..n_buckets],
+            storage: &mut guard.table.storage,
+            backend: guard.table.backend,
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+/// Gives the allocation back to the map after a parallel drain, even if the consumer panics.
+struct DrainGuard<'a, K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> {
+    table: Map<K, V, DefaultHashBuilder, A, G, P>,
+    map: &'a mut Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Drop
+    for DrainGuard<'_, K, V, S, A, G, P>
+{
+    fn drop(&mut self) {
+        // Drop the entries that weren't consumed. If dropping an item panics, the allocation
+        // is freed along with `self.table` (which drops the rest), and the map is left empty.
+        for i in 0..self.table.n_buckets() {
+            if metadata::is_full(self.table.metadata[i]) {
+                self.table.metadata[i] = metadata::empty();
+                // SAFETY: the bucket was full, and we just marked it empty so it won't be
+                // dropped again.
+                unsafe { self.table.storage[i].assume_init_drop() };
+            }
+        }
+        self.map.restore_table(&mut self.table);
+    }
+}
+
+struct DrainProducer<'a, K, V, G: Group> {
+    metadata: &'a mut [Metadata],
+    storage: &'a mut [MaybeUninit<(K, V)>],
+    backend: G::Backend,
+}
+
+impl<K: Send, V: Send, G: Group> UnindexedProducer for DrainProducer<'_, K, V, G> {
+    type Item = (K, V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let Some(mid) = split_point::<G>(self.storage.len()) else {
+            return (self, None);
+        };
+        let (metadata, other_metadata) = self.metadata.split_at_mut(mid);
+        let (storage, other_storage) = self.storage.split_at_mut(mid);
+        (
+            Self {
+                metadata,
+                storage,
+                backend: self.backend,
+            },
+            Some(Self {
+                metadata: other_metadata,
+                storage: other_storage,
+                backend: self.backend,
+            }),
+        )
+    }
+
+    fn fold_with<F>(self, folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut raw = RawGroups::<G>::new(self.backend, self.metadata);
+        folder.consume_iter(std::iter::from_fn(|| {
+            let index = raw.next(self.metadata)?;
+            // Only touch the metadata after `RawGroups` has already loaded this bucket's group.
+            // The mirrored tail is reset after the whole drain.
+            self.metadata[index] = metadata::empty();
+            // SAFETY: `RawGroups` only yields indices of full buckets, and we just marked it as
+            // empty so it won't be read again.
+            Some(unsafe { self.storage[index].assume_init_read() })
+        }))
+    }
+}
+
+/// An owning parallel iterator over the entries of a [`Map`].
+pub struct IntoParIter<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    G: Group = DefaultGroup,
+    P: GrowthPolicy = DefaultGrowth,
+> {
+    map: Map<K, V, S, A, G, P>,
+}
+
+impl<K, V, S, A, G, P> ParallelIterator for IntoParIter<K, V, S, A, G, P>
+where
+    K: Send,
+    V: Send,
+    S: BuildHasher + Send,
+    A: Allocator + Clone + Send,
+    G: Group,
+    P: GrowthPolicy,
+{
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(mut self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.map.par_drain().drive_unindexed(consumer)
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy> Map<K, V, S, A, G, P> {
+    /// A parallel iterator over the keys of the map.
+    pub fn par_keys(&self) -> ParKeys<'_, K, V, G> {
+        ParKeys {
+            inner: self.par_iter_raw(),
+        }
+    }
+
+    /// A parallel iterator over the values of the map.
+    pub fn par_values(&self) -> ParValues<'_, K, V, G> {
+        ParValues {
+            inner: self.par_iter_raw(),
+        }
+    }
+
+    fn par_iter_raw(&self) -> ParIter<'_, K, V, G> {
+        ParIter {
+            metadata: &self.metadata[..self.n_buckets()],
+            storage: &self.storage,
+            backend: self.backend,
+        }
+    }
+
+    /// Clears the map in parallel, returning all key-value pairs as a parallel iterator.
+    /// Keeps the allocated memory for reuse.
+    pub fn par_drain(&mut self) -> ParDrain<'_, K, V, S, A, G, P> {
+        ParDrain { map: self }
+    }
+}
+
+impl<'a, K: Sync, V: Sync, S: BuildHasher, A: Allocator + Clone, G: Group, P: GrowthPolicy>
+    IntoParallelIterator for &'a Map<K, V, S, A, G, P>
+{
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V, G>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter_raw()
+    }
+}
+
+impl<
+        'a,
+        K: Sync + Send,
+        V: Send,
+        S: BuildHasher,
+        A: Allocator + Clone,
+        G: Group,
+        P: GrowthPolicy,
+    > IntoParallelIterator for &'a mut Map<K, V, S, A, G, P>
+{
+    type Item = (&'a K, &'a mut V);
+    type Iter = ParIterMut<'a, K, V, G>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        let n_buckets = self.n_buckets();
+        ParIterMut {
+            metadata: &self.metadata[..n_buckets],
+            storage: &mut self.storage,
+            backend: self.backend,
+        }
+    }
+}
+
+impl<K, V, S, A, G, P> IntoParallelIterator for Map<K, V, S, A, G, P>
+where
+    K: Send,
+    V: Send,
+    S: BuildHasher + Send,
+    A: Allocator + Clone + Send,
+    G: Group,
+    P: GrowthPolicy,
+{
+    type Item = (K, V);
+    type Iter = IntoParIter<K, V, S, A, G, P>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        IntoParIter { map: self }
+    }
+}
+
+impl<K, V, S, A, G, P> ParallelExtend<(K, V)> for Map<K, V, S, A, G, P>
+where
+    K: Eq + Hash + Send,
+    V: Send,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        // Collect the items in parallel, then insert them on this thread (same as hashbrown).
+        let list: LinkedList<Vec<(K, V)>> = par_iter
+            .into_par_iter()
+            .fold(Vec::new, |mut vec, item| {
+                vec.push(item);
+                vec
+            })
+            .map(|vec| LinkedList::from([vec]))
+            .reduce(LinkedList::new, |mut list, mut other| {
+                list.append(&mut other);
+                list
+            });
+
+        // Same reservation heuristic as `Extend`.
+        let len: usize = list.iter().map(Vec::len).sum();
+        let additional = if self.is_empty() {
+            len
+        } else {
+            len.div_ceil(2)
+        };
+        self.reserve(additional);
+
+        for (k, v) in list.into_iter().flatten() {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, S, A, G, P> ParallelExtend<(&'a K, &'a V)> for Map<K, V, S, A, G, P>
+where
+    K: Eq + Hash + Copy + Send + Sync,
+    V: Copy + Send + Sync,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    G: Group,
+    P: GrowthPolicy,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (&'a K, &'a V)>,
+    {
+        self.par_extend(par_iter.into_par_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use ::rayon::prelude::*;
+
+    use crate::fifth::Map;
+
+    fn map_of(n: usize) -> Map<usize, usize> {
+        (0..n).map(|i| (i, i)).collect()
+    }
+
+    #[test]
+    fn par_iter() {
+        for n in [0, 1, 15, 16, 17, 1000, 10_000] {
+            let map = map_of(n);
+            assert_eq!(map.par_iter().count(), n);
+            assert_eq!(
+                map.par_iter().map(|(k, v)| k + v).sum::<usize>(),
+                n * n.saturating_sub(1)
+            );
+
+            let mut keys: Vec<_> = map.par_keys().copied().collect();
+            keys.sort();
+            assert_eq!(keys, (0..n).collect::<Vec<_>>());
+            assert_eq!(
+                map.par_values().copied().sum::<usize>(),
+                (0..n).sum::<usize>()
+            );
+        }
+    }
+
+    #[test]
+    fn par_iter_skips_removed() {
+        let mut map = map_of(10_000);
+        map.retain(|k, _| k % 3 == 0);
+        assert_eq!(map.par_iter().count(), map.len());
+        assert!(map.par_keys().all(|k| k % 3 == 0));
+    }
+
+    #[test]
+    fn par_iter_mut() {
+        let mut map = map_of(10_000);
+        map.par_iter_mut().for_each(|(k, v)| *v = k * 2);
+        for i in 0..10_000 {
+            assert_eq!(map[&i], i * 2);
+        }
+    }
+
+    #[test]
+    fn into_par_iter() {
+        let map: Map<usize, String> = (0..10_000).map(|i| (i, i.to_string())).collect();
+        let mut items: Vec<_> = map.into_par_iter().collect();
+        items.sort();
+        assert_eq!(items.len(), 10_000);
+        for (i, (k, v)) in items.into_iter().enumerate() {
+            assert_eq!(k, i);
+            assert_eq!(v, i.to_string());
+        }
+    }
+
+    #[test]
+    fn par_drain() {
+        let mut map: Map<usize, String> = (0..10_000).map(|i| (i, i.to_string())).collect();
+        let buckets = map.n_buckets();
+
+        assert_eq!(map.par_drain().count(), 10_000);
+        assert!(map.is_empty());
+        assert_eq!(map.n_buckets(), buckets);
+        assert_eq!(map.get(&0), None);
+
+        for i in 0..10_000 {
+            map.insert(i, i.to_string());
+        }
+        assert_eq!(map.n_buckets(), buckets);
+
+        // Stopping early still drops the rest, and leaves the map empty.
+        assert!(map.par_drain().any(|(k, _)| k == 5000));
+        assert!(map.is_empty());
+        map.insert(1, String::from("1"));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn par_drain_drops_everything() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut map = Map::new();
+        for i in 0..10_000 {
+            map.insert(i, Counted(counter.clone()));
+        }
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            map.par_drain().for_each(|(k, _)| {
+                if k == 5000 {
+                    panic!("oops");
+                }
+            })
+        }));
+        assert!(result.is_err());
+        assert!(map.is_empty());
+        assert_eq!(counter.load(Ordering::Relaxed), 10_000);
+
+        map.insert(0, Counted(counter.clone()));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn par_iter_small_groups() {
+        use std::alloc::Global;
+
+        use crate::group::SwarGroup;
+        use crate::testing::Quadrupling;
+        use crate::DefaultHashBuilder;
+
+        // SWAR groups are 8 wide, and this policy allows tables of just one group.
+        type SmallMap = Map<usize, usize, DefaultHashBuilder, Global, SwarGroup, Quadrupling>;
+
+        for n in [1, 5, 6, 100, 10_000] {
+            let mut map: SmallMap = (0..n).map(|i| (i, i)).collect();
+            assert_eq!(map.par_iter().count(), n);
+            assert_eq!(
+                map.par_keys().copied().sum::<usize>(),
+                (0..n).sum::<usize>()
+            );
+
+            map.par_iter_mut().for_each(|(k, v)| *v = k * 2);
+            assert_eq!(
+                map.par_values().copied().sum::<usize>(),
+                n * n.saturating_sub(1)
+            );
+
+            assert_eq!(map.par_drain().count(), n);
+            assert!(map.is_empty());
+        }
+    }
+
+    #[test]
+    fn par_extend() {
+        let mut map = Map::new();
+        map.par_extend((0..10_000).into_par_iter().map(|i| (i, i)));
+        assert_eq!(map.len(), 10_000);
+
+        let other = map_of(20_000);
+        map.par_extend(other.par_iter());
+        assert_eq!(map.len(), 20_000);
+        for i in 0..20_000 {
+            assert_eq!(map[&i], i);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 //! A naive map with separate chaining.
 
+use alloc::boxed::Box;
+use alloc::collections::LinkedList;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
 use core::hash::{BuildHasher, Hash};
-use std::collections::LinkedList;
 
 use crate::{fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
 
@@ -18,6 +21,16 @@ impl<K, V> Map<K, V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         let capacity = fix_capacity(capacity);
 
         let storage = (0..capacity)
@@ -25,20 +38,25 @@ impl<K, V> Map<K, V> {
             .collect();
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             n_items: 0,
             storage,
         }
     }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher(S::default())
     }
 }
 
-impl<K, V> Map<K, V> {
+impl<K, V, S: BuildHasher> Map<K, V, S> {
     pub fn len(&self) -> usize {
         self.n_items
     }
@@ -47,31 +65,55 @@ impl<K, V> Map<K, V> {
         self.n_items == 0
     }
 
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.storage
+            .iter()
+            .flat_map(|bucket| bucket.iter().map(|(k, v)| (k, v)))
+    }
+
     /// Used for testing
     #[inline]
-    fn n_buckets(&self) -> usize {
+    pub(crate) fn n_buckets(&self) -> usize {
         self.storage.len()
     }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S> Map<K, V, S>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
 {
-    pub fn get(&self, k: &K) -> Option<&V> {
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let index = self.bucket_index(k);
         for (kk, vv) in self.storage.get(index)?.iter() {
-            if kk == k {
+            if kk.borrow() == k {
                 return Some(vv);
             }
         }
         None
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let index = self.bucket_index(k);
         for (kk, vv) in self.storage.get_mut(index)?.iter_mut() {
-            if kk == k {
+            if (*kk).borrow() == k {
                 return Some(vv);
             }
         }
@@ -89,7 +131,7 @@ where
         let index = self.bucket_index(&k);
         for (kk, vv) in self.storage[index].iter_mut() {
             if kk == &k {
-                return Some(std::mem::replace(vv, v));
+                return Some(core::mem::replace(vv, v));
             }
         }
 
@@ -99,12 +141,16 @@ where
         None
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let index = self.bucket_index(k);
         let mut list_index = None;
 
         for (i, (kk, _)) in self.storage.get(index)?.iter().enumerate() {
-            if kk == k {
+            if kk.borrow() == k {
                 list_index = Some(i);
                 break;
             }
@@ -119,7 +165,7 @@ where
         Some(v)
     }
 
-    fn bucket_index(&self, k: &K) -> usize {
+    fn bucket_index<Q: ?Sized + Hash>(&self, k: &Q) -> usize {
         let hash = make_hash(&self.hasher, k);
         fast_rem(hash as usize, self.n_buckets())
         // usize::rem_euclid(hash as usize, self.n_buckets())
@@ -141,7 +187,7 @@ where
         let new_storage = (0..capacity)
             .map(|_| LinkedList::new())
             .collect();
-        let old_storage = std::mem::replace(&mut self.storage, new_storage);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
 
         self.n_items = 0;
 
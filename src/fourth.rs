@@ -1,13 +1,18 @@
 //! A Swiss Tables-inspired map with metadata.
 //! This is similar to the one in `third`, except using MaybeUninit as an optimization.
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
 use core::hash::{BuildHasher, Hash};
 use core::marker::PhantomData;
-use std::mem::MaybeUninit;
+use core::mem::MaybeUninit;
 
 use crate::{fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
 
+use crate::group::{Group, TargetGroup};
 use crate::metadata::{self, Metadata};
+use crate::stats::{self, MapStats};
 
 enum ProbeResult {
     Empty(usize, u8),
@@ -31,6 +36,16 @@ impl<K, V> Map<K, V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         let capacity = fix_capacity(capacity);
 
         let storage = Box::new_uninit_slice(capacity);
@@ -41,7 +56,7 @@ impl<K, V> Map<K, V> {
             .into_boxed_slice();
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             n_items: 0,
             n_occupied: 0,
             storage,
@@ -49,21 +64,27 @@ impl<K, V> Map<K, V> {
             _ph: PhantomData,
         }
     }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher(S::default())
     }
 }
 
-impl<K, V> Clone for Map<K, V>
+impl<K, V, S> Clone for Map<K, V, S>
 where
     K: Clone,
     V: Clone,
+    S: BuildHasher + Clone,
 {
     fn clone(&self) -> Self {
-        let mut other = Self::with_capacity(self.n_buckets());
+        let mut other = Self::with_capacity_and_hasher(self.n_buckets(), self.hasher.clone());
         assert_eq!(self.n_buckets(), other.n_buckets());
 
         for (i, m) in self.metadata.iter().enumerate() {
@@ -89,7 +110,7 @@ where
     S: BuildHasher,
 {
     fn drop(&mut self) {
-        if std::mem::needs_drop::<(K, V)>() {
+        if core::mem::needs_drop::<(K, V)>() {
             for (i, &m) in self.metadata.iter().enumerate() {
                 if metadata::is_full(m) {
                     unsafe { self.storage[i].assume_init_drop() };
@@ -99,7 +120,7 @@ where
     }
 }
 
-impl<K, V> Map<K, V> {
+impl<K, V, S: BuildHasher> Map<K, V, S> {
     pub fn len(&self) -> usize {
         self.n_items
     }
@@ -108,18 +129,36 @@ impl<K, V> Map<K, V> {
         self.n_items == 0
     }
 
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.metadata
+            .iter()
+            .zip(self.storage.iter())
+            .filter(|(m, _)| metadata::is_full(**m))
+            .map(|(_, item)| {
+                // SAFETY: `self.storage[i]` is initialized whenever `self.metadata[i]` is full.
+                let (k, v) = unsafe { item.assume_init_ref() };
+                (k, v)
+            })
+    }
+
     /// Used for testing
     #[inline]
-    fn n_buckets(&self) -> usize {
+    pub(crate) fn n_buckets(&self) -> usize {
         self.storage.len()
     }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S> Map<K, V, S>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
 {
-    fn probe_find(&self, k: &K) -> ProbeResult {
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let (mut current, h2) = self.bucket_index_and_h2(k);
 
         for step in 0..self.n_buckets() {
@@ -131,7 +170,7 @@ where
             } else if metadata::is_full(meta) && metadata::h2(meta) == h2 {
                 // SAFETY: we checked the invariant that `meta.is_value()`.
                 let (kk, _) = unsafe { self.storage[current].assume_init_ref() };
-                if kk == k {
+                if kk.borrow() == k {
                     return ProbeResult::Full(current);
                 }
             }
@@ -144,7 +183,11 @@ where
         }
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
@@ -155,7 +198,19 @@ where
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
@@ -185,16 +240,21 @@ where
             ProbeResult::Full(index) => {
                 // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
                 let (_, vv) = unsafe { self.storage[index].assume_init_mut() };
-                Some(std::mem::replace(vv, v))
+                Some(core::mem::replace(vv, v))
             }
         }
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
-                let old_bucket = std::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
+                let old_bucket =
+                    core::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
                 // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
                 let (_, vv) = unsafe { old_bucket.assume_init() };
                 self.metadata[index] = metadata::tombstone();
@@ -204,13 +264,32 @@ where
         }
     }
 
-    fn bucket_index_and_h2(&self, k: &K) -> (usize, u8) {
+    fn bucket_index_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> (usize, u8) {
         let hash = make_hash(&self.hasher, k);
         let (h1, h2) = (hash >> 7, (hash & 0x7F) as u8);
         let index = fast_rem(h1 as usize, self.n_buckets());
         (index, h2)
     }
 
+    /// Returns statistics about how the items are laid out in the buckets.
+    ///
+    /// This looks at every bucket and hashes every key, so it's slow.
+    pub fn stats(&self) -> MapStats {
+        let n_buckets = self.n_buckets();
+        let probes = self
+            .metadata
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| metadata::is_full(m))
+            .map(|(i, _)| {
+                // SAFETY: `storage[i]` is initialized since `metadata[i]` is full.
+                let (k, _) = unsafe { self.storage[i].assume_init_ref() };
+                let (home, _) = self.bucket_index_and_h2(k);
+                stats::bucket_probe_length(home, i, n_buckets, TargetGroup::WIDTH)
+            });
+        MapStats::new(&self.metadata, TargetGroup::WIDTH, probes)
+    }
+
     fn needs_resize(&self) -> bool {
         // Using a load factor of 7/8.
         // NOTE: we need to use n_occupied instead of n_items here!
@@ -226,7 +305,7 @@ where
 
         // Set `self.storage` to a new array.
         let new_storage = Box::new_uninit_slice(capacity);
-        let old_storage = std::mem::replace(&mut self.storage, new_storage);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
         let old_buckets = Vec::from(old_storage).into_iter();
 
         let new_metadata = (0..capacity)
@@ -235,7 +314,7 @@ where
             .into_boxed_slice();
         // Here, we need to keep the old metadata, as it's unsafe to blindly access the old storage
         // array.
-        let old_metadata = std::mem::replace(&mut self.metadata, new_metadata);
+        let old_metadata = core::mem::replace(&mut self.metadata, new_metadata);
 
         self.n_items = 0;
         self.n_occupied = 0;
@@ -255,5 +334,6 @@ where
 mod tests {
     use crate::fourth::Map;
     crate::generate_tests!(Map, true);
+    crate::generate_stats_tests!(Map);
     crate::generate_non_alloc_tests!(Map);
 }
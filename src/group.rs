@@ -0,0 +1,183 @@
+//! Groups of metadata bytes that are probed all at once.
+//!
+//! [`fifth::Map`](crate::fifth::Map) is generic over the group it probes with:
+//! - [`SimdGroup<16>`] loads 16 bytes into a SIMD vector (SSE2 or NEON).
+//! - [`SimdGroup<32>`] loads 32 bytes, which fits in one AVX2 register.
+//! - [`SwarGroup`] loads 8 bytes into a `u64`, for targets without 128-bit SIMD.
+//! - [`DetectedGroup`] is 32 bytes wide and picks one of the above when the program runs.
+//!
+//! By default, it uses [`DetectedGroup`] on x86 with `std`, and [`TargetGroup`] everywhere else.
+//! The other maps that probe groups always use [`TargetGroup`].
+
+mod detect;
+mod sse;
+mod swar;
+
+pub use detect::{backend, Backend, DetectedBackend, DetectedGroup};
+pub use sse::SimdGroup;
+pub use swar::{SwarBitMask, SwarGroup};
+
+use crate::metadata::Metadata;
+
+cfg_select! {
+    any(
+        feature = "swar",
+        not(any(target_feature = "sse2", target_feature = "neon", target_feature = "simd128")),
+    ) => {
+        /// The best group that the target is compiled for.
+        ///
+        /// This is [`SwarGroup`], since the target doesn't have 128-bit SIMD
+        /// (or the `swar` feature is enabled).
+        pub type TargetGroup = SwarGroup;
+    }
+    _ => {
+        /// The best group that the target is compiled for.
+        pub type TargetGroup = SimdGroup<16>;
+    }
+}
+
+cfg_select! {
+    all(
+        not(feature = "swar"),
+        feature = "std",
+        any(target_arch = "x86", target_arch = "x86_64"),
+    ) => {
+        /// The group that [`fifth::Map`](crate::fifth::Map) probes with unless another one is
+        /// chosen.
+        ///
+        /// This is [`DetectedGroup`], since x86 CPUs differ in whether they have AVX2, and `std`
+        /// can check when the program runs.
+        pub type DefaultGroup = DetectedGroup;
+    }
+    _ => {
+        /// The group that [`fifth::Map`](crate::fifth::Map) probes with unless another one is
+        /// chosen.
+        ///
+        /// This is [`TargetGroup`], since there's nothing to detect at runtime.
+        pub type DefaultGroup = TargetGroup;
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A group of metadata bytes that can be matched all at once.
+///
+/// This trait is sealed: the only groups are [`SimdGroup<16>`], [`SimdGroup<32>`],
+/// [`SwarGroup`] and [`DetectedGroup`].
+pub trait Group: Copy + Send + Sync + sealed::Sealed {
+    /// The number of buckets in a group.
+    const WIDTH: usize;
+
+    /// The matching buckets in a group.
+    type BitMask: BitMask;
+
+    /// What matching a group depends on besides its bytes, chosen once when a map is created.
+    ///
+    /// This is `()` for the groups that are picked at compile time, and a [`DetectedBackend`]
+    /// for [`DetectedGroup`].
+    type Backend: Copy + Send + Sync;
+
+    /// Chooses the backend for a new map.
+    fn detect_backend() -> Self::Backend;
+
+    /// Loads a group from the first `WIDTH` bytes of `metadata`, to be matched with `backend`.
+    fn load(backend: Self::Backend, metadata: &[Metadata]) -> Self;
+
+    /// Loads a group from the first `WIDTH` bytes of `metadata`.
+    ///
+    /// This chooses the backend every time, so maps hold on to one and call `load` instead.
+    #[inline]
+    fn from_slice(metadata: &[Metadata]) -> Self {
+        Self::load(Self::detect_backend(), metadata)
+    }
+
+    fn to_empties(self) -> Self::BitMask;
+
+    fn to_fulls(self) -> Self::BitMask;
+
+    fn to_empties_or_tombstones(self) -> Self::BitMask;
+
+    fn to_candidates(self, h2: u8) -> Self::BitMask;
+}
+
+/// A set of bucket indices within a [`Group`].
+pub trait BitMask: Copy + sealed::Sealed {
+    /// The mask with no buckets in it.
+    const NONE: Self;
+
+    /// The index of the first bucket in the mask.
+    fn lowest(self) -> Option<usize>;
+
+    /// The index of the last bucket in the mask.
+    fn highest(self) -> Option<usize>;
+
+    /// Removes the bucket at `index` from the mask.
+    fn remove(self, index: usize) -> Self;
+}
+
+#[derive(Clone)]
+pub struct MaskIter<M, D> {
+    inner: M,
+    _direction: D,
+}
+
+#[derive(Clone)]
+pub struct Forward;
+#[derive(Clone)]
+pub struct Reverse;
+
+impl<M: BitMask> MaskIter<M, Forward> {
+    #[inline]
+    pub fn forward(mask: M) -> Self {
+        Self {
+            inner: mask,
+            _direction: Forward,
+        }
+    }
+}
+
+impl<M: BitMask> MaskIter<M, Reverse> {
+    #[inline]
+    pub fn reverse(mask: M) -> Self {
+        Self {
+            inner: mask,
+            _direction: Reverse,
+        }
+    }
+}
+
+impl<M: BitMask> Iterator for MaskIter<M, Forward> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self.inner.lowest()?;
+        self.inner = self.inner.remove(i);
+        Some(i)
+    }
+}
+
+impl<M: BitMask> Iterator for MaskIter<M, Reverse> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self.inner.highest()?;
+        self.inner = self.inner.remove(i);
+        Some(i)
+    }
+}
+
+/// Find the first set bit in the mask.
+#[inline]
+pub fn find_first<M: BitMask>(mask: M) -> Option<usize> {
+    mask.lowest()
+}
+
+/// Find the last set bit in the mask.
+#[inline]
+pub fn find_last<M: BitMask>(mask: M) -> Option<usize> {
+    mask.highest()
+}
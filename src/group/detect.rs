@@ -0,0 +1,237 @@
+//! Defines a group that picks its probing backend from the CPU at runtime.
+//!
+//! The group is always 32 buckets wide, so the layout of a map doesn't depend on the CPU.
+//! Each operation runs as one AVX2 vector, two 16-byte vectors (SSE2 on x86), or four SWAR
+//! words, whichever is the fastest that the CPU supports. The CPU is checked once per
+//! process, and each map keeps the answer from when it was created, so matching a group
+//! only branches on a value the map already has at hand.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::sealed::Sealed;
+use super::{Group, SimdGroup, SwarBitMask, SwarGroup};
+use crate::metadata::Metadata;
+
+const GROUP_SIZE: usize = 32;
+
+/// The ways that [`DetectedGroup`] can match a group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Backend {
+    /// One 32-byte vector (AVX2).
+    Simd32 = 1,
+    /// Two 16-byte vectors (SSE2, or NEON and `simd128` on other targets).
+    Simd16 = 2,
+    /// Four 8-byte words, for CPUs without 128-bit SIMD.
+    Swar = 3,
+}
+
+/// The detected backend, or 0 if detection hasn't run yet.
+static BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the backend that [`DetectedGroup`] uses on this CPU.
+#[inline]
+pub fn backend() -> Backend {
+    match BACKEND.load(Ordering::Relaxed) {
+        1 => Backend::Simd32,
+        2 => Backend::Simd16,
+        3 => Backend::Swar,
+        _ => {
+            // Racing threads detect the same backend, so it doesn't matter who stores it.
+            let backend = detect();
+            BACKEND.store(backend as u8, Ordering::Relaxed);
+            backend
+        }
+    }
+}
+
+#[cold]
+fn detect() -> Backend {
+    cfg_select! {
+        all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")) => {
+            if std::is_x86_feature_detected!("avx2") {
+                Backend::Simd32
+            } else if std::is_x86_feature_detected!("sse2") {
+                Backend::Simd16
+            } else {
+                Backend::Swar
+            }
+        }
+        // Without `std` we can't ask the CPU, so go by what the target promises.
+        target_feature = "avx2" => Backend::Simd32,
+        any(target_feature = "sse2", target_feature = "neon", target_feature = "simd128") => {
+            Backend::Simd16
+        }
+        _ => Backend::Swar,
+    }
+}
+
+/// Matches a 32-byte group as a single AVX2 vector.
+///
+/// # Safety
+///
+/// The CPU must support AVX2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn simd32(bytes: &[Metadata; GROUP_SIZE], op: impl Fn(SimdGroup<32>) -> u32) -> u32 {
+    op(SimdGroup::from_slice(bytes))
+}
+
+/// Matches a 32-byte group as two 16-byte vectors.
+///
+/// # Safety
+///
+/// The CPU must support SSE2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+#[inline]
+unsafe fn simd16(bytes: &[Metadata; GROUP_SIZE], op: impl Fn(SimdGroup<32>) -> u32) -> u32 {
+    op(SimdGroup::from_slice(bytes))
+}
+
+/// Matches a 32-byte group as two 16-byte vectors.
+///
+/// # Safety
+///
+/// Always safe; this is `unsafe` to match the x86 version.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[inline]
+unsafe fn simd16(bytes: &[Metadata; GROUP_SIZE], op: impl Fn(SimdGroup<32>) -> u32) -> u32 {
+    op(SimdGroup::from_slice(bytes))
+}
+
+/// Matches a 32-byte group as four SWAR groups.
+#[inline]
+fn swar(bytes: &[Metadata; GROUP_SIZE], op: impl Fn(SwarGroup) -> SwarBitMask) -> u32 {
+    bytes
+        .chunks_exact(SwarGroup::WIDTH)
+        .rev()
+        .fold(0, |mask, chunk| {
+            (mask << SwarGroup::WIDTH) | op(SwarGroup::from_slice(chunk)).to_bits() as u32
+        })
+}
+
+/// A [`Backend`] that the CPU is known to support.
+///
+/// The only way to get one is [`DetectedGroup::detect_backend`](Group::detect_backend), so
+/// a group can't be matched with instructions that the CPU doesn't have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DetectedBackend(Backend);
+
+/// A group of 32 metadata bytes, matched with the fastest [`Backend`] the CPU supports.
+///
+/// This gets AVX2 probing on CPUs that have it, without compiling with
+/// `-C target-feature=+avx2`.
+#[derive(Clone, Copy)]
+pub struct DetectedGroup {
+    bytes: [Metadata; GROUP_SIZE],
+    backend: DetectedBackend,
+}
+
+/// Calls `$method` on the group using its backend.
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr)?)) => {
+        match $self.backend.0 {
+            // SAFETY: `Simd32` is only detected if the CPU supports AVX2.
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Simd32 => unsafe { simd32(&$self.bytes, |g| g.$method($($arg)?)) },
+            // SAFETY: `Simd16` is only detected on x86 if the CPU supports SSE2.
+            Backend::Simd16 => unsafe { simd16(&$self.bytes, |g| g.$method($($arg)?)) },
+            Backend::Swar => swar(&$self.bytes, |g| g.$method($($arg)?)),
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            Backend::Simd32 => unreachable!(),
+        }
+    };
+}
+
+impl Sealed for DetectedGroup {}
+
+impl Group for DetectedGroup {
+    const WIDTH: usize = GROUP_SIZE;
+    type BitMask = u32;
+    type Backend = DetectedBackend;
+
+    #[inline]
+    fn detect_backend() -> DetectedBackend {
+        DetectedBackend(backend())
+    }
+
+    #[inline]
+    fn load(backend: DetectedBackend, metadata: &[Metadata]) -> Self {
+        Self {
+            bytes: metadata[..GROUP_SIZE].try_into().unwrap(),
+            backend,
+        }
+    }
+
+    #[inline]
+    fn to_empties(self) -> u32 {
+        dispatch!(self.to_empties())
+    }
+
+    #[inline]
+    fn to_fulls(self) -> u32 {
+        dispatch!(self.to_fulls())
+    }
+
+    #[inline]
+    fn to_empties_or_tombstones(self) -> u32 {
+        dispatch!(self.to_empties_or_tombstones())
+    }
+
+    #[inline]
+    fn to_candidates(self, h2: u8) -> u32 {
+        dispatch!(self.to_candidates(h2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simd16, swar, GROUP_SIZE};
+    use crate::group::{DetectedGroup, Group, SimdGroup};
+    use crate::metadata;
+
+    #[test]
+    fn backends_agree() {
+        let mut bytes = [metadata::empty(); GROUP_SIZE];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = match i % 7 {
+                0 => metadata::empty(),
+                3 => metadata::tombstone(),
+                _ => metadata::from_h2(i as u8 % 4),
+            };
+        }
+
+        let group = DetectedGroup::from_slice(&bytes);
+        let simd = SimdGroup::<32>::from_slice(&bytes);
+        for h2 in 0..4 {
+            let expected = simd.to_candidates(h2);
+            assert_eq!(group.to_candidates(h2), expected);
+            assert_eq!(unsafe { simd16(&bytes, |g| g.to_candidates(h2)) }, expected);
+            assert_eq!(swar(&bytes, |g| g.to_candidates(h2)), expected);
+        }
+        assert_eq!(group.to_empties(), simd.to_empties());
+        assert_eq!(swar(&bytes, |g| g.to_empties()), simd.to_empties());
+        assert_eq!(group.to_fulls(), simd.to_fulls());
+        assert_eq!(swar(&bytes, |g| g.to_fulls()), simd.to_fulls());
+        assert_eq!(
+            swar(&bytes, |g| g.to_empties_or_tombstones()),
+            simd.to_empties_or_tombstones()
+        );
+    }
+
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    #[test]
+    fn detects_x86_64() {
+        use crate::group::{backend, Backend};
+
+        let expected = if std::is_x86_feature_detected!("avx2") {
+            Backend::Simd32
+        } else {
+            Backend::Simd16
+        };
+        assert_eq!(backend(), expected);
+        // The second call reads the cached backend.
+        assert_eq!(backend(), expected);
+    }
+}
@@ -0,0 +1,141 @@
+//! Defines the groups for SIMD probing, with 16 or 32 buckets per group.
+use core::simd::{cmp::SimdPartialEq, Mask, Simd};
+
+use super::sealed::Sealed;
+use super::{BitMask, Group};
+use crate::metadata::{self, Metadata};
+
+pub type SimdType<const N: usize> = Simd<u8, N>;
+pub type MaskType<const N: usize> = Mask<i8, N>;
+
+/// A group of `N` metadata bytes in a SIMD vector.
+///
+/// `N` is 16 or 32. A 32-byte group takes two instructions for each operation unless the
+/// target has AVX2, so it's mostly useful when compiling with `-C target-feature=+avx2`.
+#[derive(Clone, Copy)]
+pub struct SimdGroup<const N: usize>(SimdType<N>);
+
+impl<const N: usize> SimdGroup<N> {
+    #[inline]
+    fn empties(self) -> MaskType<N> {
+        let empty = SimdType::splat(metadata::empty());
+        empty.simd_eq(self.0)
+    }
+
+    #[inline]
+    fn fulls(self) -> MaskType<N> {
+        let empty_mask = SimdType::splat(metadata::empty());
+        let zeros = SimdType::splat(0);
+        (empty_mask & self.0).simd_eq(zeros)
+    }
+
+    #[inline]
+    fn candidates(self, h2: u8) -> MaskType<N> {
+        let h2 = SimdType::splat(h2);
+        h2.simd_eq(self.0)
+    }
+}
+
+/// Implements `Group` for `SimdGroup<$width>`, with a `$bitmask` bit per bucket.
+macro_rules! impl_simd_group {
+    ($width:literal, $bitmask:ty) => {
+        impl Sealed for SimdGroup<$width> {}
+
+        impl Group for SimdGroup<$width> {
+            const WIDTH: usize = $width;
+            type BitMask = $bitmask;
+            type Backend = ();
+
+            #[inline]
+            fn detect_backend() {}
+
+            #[inline]
+            fn load(_: (), metadata: &[Metadata]) -> Self {
+                Self(SimdType::from_slice(metadata))
+            }
+
+            #[inline]
+            fn to_empties(self) -> $bitmask {
+                self.empties().to_bitmask() as $bitmask
+            }
+
+            #[inline]
+            fn to_fulls(self) -> $bitmask {
+                self.fulls().to_bitmask() as $bitmask
+            }
+
+            #[inline]
+            fn to_empties_or_tombstones(self) -> $bitmask {
+                (!self.fulls()).to_bitmask() as $bitmask
+            }
+
+            #[inline]
+            fn to_candidates(self, h2: u8) -> $bitmask {
+                self.candidates(h2).to_bitmask() as $bitmask
+            }
+        }
+
+        impl Sealed for $bitmask {}
+
+        impl BitMask for $bitmask {
+            const NONE: Self = 0;
+
+            #[inline]
+            fn lowest(self) -> Option<usize> {
+                (self != 0).then(|| self.trailing_zeros() as usize)
+            }
+
+            #[inline]
+            fn highest(self) -> Option<usize> {
+                (self != 0).then(|| (<$bitmask>::BITS - 1 - self.leading_zeros()) as usize)
+            }
+
+            #[inline]
+            fn remove(self, index: usize) -> Self {
+                self & !(1 << index)
+            }
+        }
+    };
+}
+
+impl_simd_group!(16, u16);
+impl_simd_group!(32, u32);
+
+#[cfg(test)]
+mod tests {
+    use crate::group::{find_first, find_last, Group, MaskIter, SimdGroup};
+    use crate::metadata;
+
+    #[test]
+    fn wide_matches_narrow() {
+        let mut bytes = [metadata::empty(); 32];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = match i % 5 {
+                0 => metadata::empty(),
+                1 => metadata::tombstone(),
+                _ => metadata::from_h2(i as u8 % 3),
+            };
+        }
+
+        let wide = SimdGroup::<32>::from_slice(&bytes);
+        let narrow = [0, 16].map(|half| SimdGroup::<16>::from_slice(&bytes[half..]));
+        let both_halves = |mask: fn(SimdGroup<16>) -> u16| -> Vec<usize> {
+            let [lo, hi] = narrow.map(|g| MaskIter::forward(mask(g)));
+            lo.chain(hi.map(|i| i + 16)).collect()
+        };
+
+        let fulls: Vec<_> = MaskIter::forward(wide.to_fulls()).collect();
+        assert_eq!(fulls, both_halves(SimdGroup::to_fulls));
+        let empties: Vec<_> = MaskIter::forward(wide.to_empties()).collect();
+        assert_eq!(empties, both_halves(SimdGroup::to_empties));
+        let candidates: Vec<_> = MaskIter::forward(wide.to_candidates(2)).collect();
+        assert_eq!(candidates, both_halves(|g| g.to_candidates(2)));
+        let mut reverse: Vec<_> = MaskIter::reverse(wide.to_empties_or_tombstones()).collect();
+        reverse.reverse();
+        assert_eq!(reverse, both_halves(SimdGroup::to_empties_or_tombstones));
+
+        assert_eq!(find_first(wide.to_empties()), Some(0));
+        assert_eq!(find_last(wide.to_empties()), Some(30));
+        assert_eq!(find_last(wide.to_candidates(0x7F)), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Defines the group for SWAR ("SIMD within a register") probing.
+//!
+//! This is a fallback for targets without 128-bit SIMD. A group is 8 metadata bytes loaded
+//! into a `u64`, and each mask has the high bit of a byte set for every matching bucket.
+use super::sealed::Sealed;
+use super::{BitMask, Group};
+use crate::metadata::{self, Metadata};
+
+const GROUP_SIZE: usize = 8;
+
+/// The lowest bit of each byte.
+const LO: u64 = u64::from_ne_bytes([0x01; GROUP_SIZE]);
+/// The highest bit of each byte.
+const HI: u64 = u64::from_ne_bytes([0x80; GROUP_SIZE]);
+
+/// A group of 8 metadata bytes in a `u64`.
+#[derive(Clone, Copy)]
+pub struct SwarGroup(u64);
+
+/// The high bit of each byte is set for the matching buckets.
+#[derive(Clone, Copy)]
+pub struct SwarBitMask(u64);
+
+impl SwarGroup {
+    /// Sets the high bit of each byte that is equal to `byte`.
+    ///
+    /// This is exact: the classic `(x - LO) & !x & HI` trick can also flag the byte above
+    /// a match because of the borrow, which is fine for candidates but not for empties.
+    #[inline]
+    fn match_byte(self, byte: u8) -> SwarBitMask {
+        let x = self.0 ^ (LO * byte as u64);
+        // Adding 0x7F to the low 7 bits of each byte never carries into the next byte,
+        // and sets the high bit unless those 7 bits were all zero.
+        let nonzero = ((x & !HI) + !HI) | x;
+        SwarBitMask(!nonzero & HI)
+    }
+}
+
+impl Sealed for SwarGroup {}
+
+impl Group for SwarGroup {
+    const WIDTH: usize = GROUP_SIZE;
+    type BitMask = SwarBitMask;
+    type Backend = ();
+
+    #[inline]
+    fn detect_backend() {}
+
+    /// Loads the group as little-endian, so that bucket `i` is byte `i` of the `u64`
+    /// on every target.
+    #[inline]
+    fn load(_: (), metadata: &[Metadata]) -> Self {
+        let bytes = metadata[..GROUP_SIZE].try_into().unwrap();
+        Self(u64::from_le_bytes(bytes))
+    }
+
+    #[inline]
+    fn to_empties(self) -> SwarBitMask {
+        self.match_byte(metadata::empty())
+    }
+
+    #[inline]
+    fn to_fulls(self) -> SwarBitMask {
+        SwarBitMask(!self.0 & HI)
+    }
+
+    #[inline]
+    fn to_empties_or_tombstones(self) -> SwarBitMask {
+        SwarBitMask(self.0 & HI)
+    }
+
+    #[inline]
+    fn to_candidates(self, h2: u8) -> SwarBitMask {
+        self.match_byte(h2)
+    }
+}
+
+impl SwarBitMask {
+    /// Packs the mask into one bit per bucket, like the SIMD masks.
+    #[inline]
+    pub(super) fn to_bits(self) -> u8 {
+        // Multiplying moves the bit of byte `i` (now at bit `8 * i`) to bit `56 + i`,
+        // and the other partial products never reach the top byte.
+        ((self.0 >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u8
+    }
+}
+
+impl Sealed for SwarBitMask {}
+
+impl BitMask for SwarBitMask {
+    const NONE: Self = Self(0);
+
+    #[inline]
+    fn lowest(self) -> Option<usize> {
+        (self.0 != 0).then(|| self.0.trailing_zeros() as usize / 8)
+    }
+
+    #[inline]
+    fn highest(self) -> Option<usize> {
+        (self.0 != 0).then(|| (u64::BITS - 1 - self.0.leading_zeros()) as usize / 8)
+    }
+
+    #[inline]
+    fn remove(self, index: usize) -> Self {
+        Self(self.0 & !(0x80 << (8 * index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::group::{
+        find_first, find_last, BitMask, Group, MaskIter, SimdGroup, SwarBitMask, SwarGroup,
+    };
+    use crate::metadata;
+
+    /// Checks that a SWAR mask, applied to both halves of a SIMD group, matches the SIMD mask.
+    fn check(bytes: &[u8; 16], simd: u16, swar: impl Fn(SwarGroup) -> SwarBitMask) {
+        let expected: Vec<_> = MaskIter::forward(simd).collect();
+
+        let mut forward = vec![];
+        let mut reverse = vec![];
+        for half in [0, SwarGroup::WIDTH] {
+            let mask = swar(SwarGroup::from_slice(&bytes[half..]));
+            forward.extend(MaskIter::forward(mask).map(|i| i + half));
+            let mut rev: Vec<_> = MaskIter::reverse(mask).map(|i| i + half).collect();
+            rev.reverse();
+            reverse.extend(rev);
+        }
+        assert_eq!(forward, expected, "{bytes:x?}");
+        assert_eq!(reverse, expected, "{bytes:x?}");
+    }
+
+    fn check_matches_simd(bytes: [u8; 16], h2: u8) {
+        let simd = SimdGroup::<16>::from_slice(&bytes);
+        check(&bytes, simd.to_empties(), SwarGroup::to_empties);
+        check(&bytes, simd.to_fulls(), SwarGroup::to_fulls);
+        check(
+            &bytes,
+            simd.to_empties_or_tombstones(),
+            SwarGroup::to_empties_or_tombstones,
+        );
+        check(&bytes, simd.to_candidates(h2), |g| g.to_candidates(h2));
+    }
+
+    #[test]
+    fn matches_simd() {
+        // A small LCG, so the test is deterministic without pulling in `rand`.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 33) as u8
+        };
+
+        for _ in 0..10_000 {
+            let bytes = core::array::from_fn(|_| match next() % 4 {
+                0 => metadata::empty(),
+                1 => metadata::tombstone(),
+                // Only a few `h2`s, so that candidates often match.
+                _ => metadata::from_h2(next() % 4),
+            });
+            check_matches_simd(bytes, next() % 4);
+        }
+
+        for byte in 0..=u8::MAX {
+            check_matches_simd([byte; 16], metadata::h2(byte));
+        }
+    }
+
+    #[test]
+    fn find_first_and_last() {
+        let group = SwarGroup::from_slice(&[0x80, 1, 2, 0x80, 0xFE, 1, 0x80, 3]);
+        assert_eq!(find_first(group.to_empties()), Some(0));
+        assert_eq!(find_last(group.to_empties()), Some(6));
+        assert_eq!(find_first(group.to_candidates(1)), Some(1));
+        assert_eq!(find_last(group.to_candidates(1)), Some(5));
+        assert_eq!(find_first(group.to_candidates(4)), None);
+        assert_eq!(find_first(SwarBitMask::NONE), None);
+
+        let fulls: Vec<_> = MaskIter::forward(group.to_fulls()).collect();
+        assert_eq!(fulls, [1, 2, 5, 7]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Policies that decide how full a map gets before it grows, and how much it grows by.
+//!
+//! [`fifth::Map`](crate::fifth::Map) and [`sixth::Map`](crate::sixth::Map) take a policy as
+//! their last type parameter. A higher load factor saves memory but makes probes longer, and a
+//! lower one does the opposite.
+use crate::{handle_reserve_error, TryReserveError};
+
+/// Decides when a map grows or shrinks.
+///
+/// Every constant has a default, so a policy only needs to override the ones it cares about.
+/// The constants are checked when the map is compiled.
+pub trait GrowthPolicy {
+    /// The largest fraction of the buckets that may be occupied, as `(numerator, denominator)`.
+    ///
+    /// Tombstones count as occupied. This must be less than one, so that every probe ends at
+    /// an empty bucket.
+    const MAX_LOAD_FACTOR: (usize, usize) = (7, 8);
+
+    /// How many times more buckets to allocate when the map is full.
+    ///
+    /// Must be a power of two, and at least 2.
+    const GROWTH_FACTOR: usize = 2;
+
+    /// The fewest buckets to allocate, once the map allocates at all.
+    ///
+    /// Must be a power of two. Maps round this up to a whole group.
+    const MIN_BUCKETS: usize = 16;
+
+    /// If removing an item leaves less than this fraction of the buckets full, the map shrinks
+    /// to fit its items, as `(numerator, denominator)`.
+    ///
+    /// Must be less than `MAX_LOAD_FACTOR / GROWTH_FACTOR`, or the map could shrink right after
+    /// growing. Only the map's and its entries' `remove` methods shrink it; `retain`,
+    /// `extract_if` and `drain` don't. The default of `None` never shrinks.
+    const SHRINK_LOAD_FACTOR: Option<(usize, usize)> = None;
+}
+
+/// The policy the maps use unless told otherwise: a load factor of 7/8, doubling when full,
+/// at least 16 buckets, and never shrinking on its own.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultGrowth;
+
+impl GrowthPolicy for DefaultGrowth {}
+
+/// `n * numerator / denominator` rounded down, without overflowing.
+#[inline]
+const fn scale(n: usize, (numerator, denominator): (usize, usize)) -> usize {
+    n / denominator * numerator + n % denominator * numerator / denominator
+}
+
+/// The number of buckets out of `n_buckets` that may be occupied before the map grows.
+#[inline]
+pub(crate) fn bucket_capacity<P: GrowthPolicy>(n_buckets: usize) -> usize {
+    const {
+        let (numerator, denominator) = P::MAX_LOAD_FACTOR;
+        assert!(
+            0 < numerator && numerator < denominator,
+            "the max load factor must be between 0 and 1"
+        );
+    }
+    scale(n_buckets, P::MAX_LOAD_FACTOR)
+}
+
+/// Choose an actual number of buckets from the requested one.
+pub(crate) fn fix_capacity<P: GrowthPolicy>(capacity: usize) -> usize {
+    try_fix_capacity::<P>(capacity).unwrap_or_else(|e| handle_reserve_error(e))
+}
+
+/// Same as `fix_capacity`, but returns an error instead of panicking on overflow.
+pub(crate) fn try_fix_capacity<P: GrowthPolicy>(capacity: usize) -> Result<usize, TryReserveError> {
+    const {
+        assert!(
+            P::MIN_BUCKETS.is_power_of_two(),
+            "the minimum number of buckets must be a power of two"
+        );
+    }
+    match capacity {
+        0 => Ok(0),
+        x if x < P::MIN_BUCKETS => Ok(P::MIN_BUCKETS),
+        x => x
+            .checked_next_power_of_two()
+            .ok_or(TryReserveError::CapacityOverflow),
+    }
+}
+
+/// The number of buckets needed to hold `capacity` items without growing.
+pub(crate) fn capacity_to_buckets<P: GrowthPolicy>(
+    capacity: usize,
+) -> Result<usize, TryReserveError> {
+    let (numerator, denominator) = P::MAX_LOAD_FACTOR;
+    let adjusted = capacity
+        .checked_mul(denominator)
+        .ok_or(TryReserveError::CapacityOverflow)?
+        .div_ceil(numerator);
+    try_fix_capacity::<P>(adjusted)
+}
+
+/// The number of buckets to grow to from `n_buckets`, which isn't zero.
+pub(crate) fn grow<P: GrowthPolicy>(n_buckets: usize) -> usize {
+    const {
+        assert!(
+            P::GROWTH_FACTOR >= 2 && P::GROWTH_FACTOR.is_power_of_two(),
+            "the growth factor must be a power of two, and at least 2"
+        );
+    }
+    n_buckets
+        .checked_mul(P::GROWTH_FACTOR)
+        .unwrap_or_else(|| handle_reserve_error(TryReserveError::CapacityOverflow))
+}
+
+/// Whether a map with `n_items` items in `n_buckets` buckets should shrink after a removal.
+#[inline]
+pub(crate) fn should_shrink<P: GrowthPolicy>(n_items: usize, n_buckets: usize) -> bool {
+    const {
+        if let Some((numerator, denominator)) = P::SHRINK_LOAD_FACTOR {
+            let (max_numerator, max_denominator) = P::MAX_LOAD_FACTOR;
+            assert!(
+                numerator * P::GROWTH_FACTOR * max_denominator < max_numerator * denominator,
+                "the shrink load factor must be less than the max load factor over the growth factor"
+            );
+        }
+    }
+    match P::SHRINK_LOAD_FACTOR {
+        None => false,
+        Some(factor) => n_buckets > P::MIN_BUCKETS && n_items < scale(n_buckets, factor),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_capacity, capacity_to_buckets, fix_capacity, grow, should_shrink};
+    use super::{DefaultGrowth, GrowthPolicy};
+
+    struct Sparse;
+
+    impl GrowthPolicy for Sparse {
+        const MAX_LOAD_FACTOR: (usize, usize) = (1, 2);
+        const GROWTH_FACTOR: usize = 4;
+        const MIN_BUCKETS: usize = 8;
+        const SHRINK_LOAD_FACTOR: Option<(usize, usize)> = Some((1, 10));
+    }
+
+    #[test]
+    fn default_growth() {
+        assert_eq!(fix_capacity::<DefaultGrowth>(0), 0);
+        assert_eq!(fix_capacity::<DefaultGrowth>(3), 16);
+        assert_eq!(fix_capacity::<DefaultGrowth>(17), 32);
+        assert_eq!(bucket_capacity::<DefaultGrowth>(64), 56);
+        assert_eq!(capacity_to_buckets::<DefaultGrowth>(14), Ok(16));
+        assert_eq!(capacity_to_buckets::<DefaultGrowth>(15), Ok(32));
+        assert!(!should_shrink::<DefaultGrowth>(0, 1024));
+    }
+
+    #[test]
+    fn custom_growth() {
+        assert_eq!(fix_capacity::<Sparse>(3), 8);
+        assert_eq!(bucket_capacity::<Sparse>(64), 32);
+        assert_eq!(capacity_to_buckets::<Sparse>(32), Ok(64));
+        assert_eq!(capacity_to_buckets::<Sparse>(33), Ok(128));
+        assert_eq!(grow::<Sparse>(64), 256);
+        assert!(should_shrink::<Sparse>(5, 64));
+        assert!(!should_shrink::<Sparse>(6, 64));
+        // Never shrinks below the minimum.
+        assert!(!should_shrink::<Sparse>(0, 8));
+    }
+}
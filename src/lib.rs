@@ -1,47 +1,115 @@
 #![feature(
-    new_uninit,
     allocator_api,
-    iter_array_chunks,
     dropck_eyepatch,
     slice_ptr_get,
     portable_simd,
-    core_intrinsics
+    likely_unlikely,
+    min_specialization,
+    trivial_clone
 )]
+#![cfg_attr(feature = "std", feature(mapped_lock_guards))]
+// Tests always have `std`, so they can use its prelude.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
-use core::hash::{BuildHasher, Hasher};
-use std::collections::hash_map::DefaultHasher;
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use core::fmt;
+use core::hash::BuildHasher;
 
 #[rustfmt::skip]
 pub mod first;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod eighth;
 pub mod fifth;
 pub mod fourth;
+pub mod group;
+pub mod growth;
+pub mod ninth;
 pub mod second;
+pub mod set;
+pub mod seventh;
 pub mod sixth;
 pub mod third;
 
 mod metadata;
-mod sse;
+#[cfg(feature = "serde")]
+mod serde;
+mod stats;
+
+pub use stats::MapStats;
 
 /// Hash builder for std's default hasher.
-pub type DefaultHashBuilder = core::hash::BuildHasherDefault<DefaultHasher>;
+#[cfg(feature = "std")]
+pub type DefaultHashBuilder = core::hash::BuildHasherDefault<std::hash::DefaultHasher>;
+
+/// Hash builder for SipHash-2-4 with fixed keys.
+///
+/// Without `std`, this is the only hasher available. It's deprecated in `core` in favor of
+/// std's `DefaultHasher`, which is a faster SipHash-1-3.
+#[cfg(not(feature = "std"))]
+#[allow(deprecated)]
+pub type DefaultHashBuilder = core::hash::BuildHasherDefault<core::hash::SipHasher>;
 
 /// Convenience function for hashing a key.
-fn make_hash<S, K>(build_hasher: &S, key: &K) -> u64
+fn make_hash<S, Q>(build_hasher: &S, key: &Q) -> u64
 where
     S: BuildHasher,
-    K: core::hash::Hash,
+    Q: ?Sized + core::hash::Hash,
 {
-    let mut hasher = build_hasher.build_hasher();
-    key.hash(&mut hasher);
-    hasher.finish()
+    build_hasher.hash_one(key)
+}
+
+/// Mixes a hash so that every bit of it depends on every bit of the original.
+///
+/// Maps that take their buckets from the high bits of a hash need this, since weak hashers
+/// (like the identity hash of an integer) leave those bits all zero.
+#[inline]
+fn mix_hash(hash: u64) -> u64 {
+    let full = (hash as u128) * 0x9E37_79B9_7F4A_7C15;
+    (full as u64) ^ ((full >> 64) as u64)
 }
 
-/// Choose an actual capacity from the requested one.
+/// Choose an actual capacity from the requested one, using the default growth policy.
 fn fix_capacity(capacity: usize) -> usize {
-    match capacity {
-        0 => 0,
-        x if x < 16 => 16,
-        x => x.next_power_of_two(),
+    growth::fix_capacity::<growth::DefaultGrowth>(capacity)
+}
+
+/// The number of buckets needed to hold `capacity` items, using the default growth policy.
+fn capacity_to_buckets(capacity: usize) -> usize {
+    growth::capacity_to_buckets::<growth::DefaultGrowth>(capacity)
+        .unwrap_or_else(|e| handle_reserve_error(e))
+}
+
+/// The error type for `try_reserve` methods.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TryReserveError {
+    /// The required capacity overflowed `usize`, or the allocation would be too large.
+    CapacityOverflow,
+    /// The allocator returned an error.
+    AllocError { layout: Layout },
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryReserveError::CapacityOverflow => write!(f, "capacity overflow"),
+            TryReserveError::AllocError { layout } => {
+                write!(f, "memory allocation of {} bytes failed", layout.size())
+            }
+        }
+    }
+}
+
+impl core::error::Error for TryReserveError {}
+
+/// Panic or abort on an allocation failure, the same way std's collections do.
+#[cold]
+fn handle_reserve_error(e: TryReserveError) -> ! {
+    match e {
+        TryReserveError::CapacityOverflow => panic!("capacity overflow"),
+        TryReserveError::AllocError { layout } => alloc::alloc::handle_alloc_error(layout),
     }
 }
 
@@ -51,12 +119,13 @@ fn fast_rem(n: usize, modulus_power_of_two: usize) -> usize {
 }
 
 pub use fifth::Map as CbHashMap;
+pub use set::Set as CbHashSet;
 
 pub fn get<'a>(map: &'a CbHashMap<usize, usize>, k: &'a usize) -> Option<&'a usize> {
     map.get(k)
 }
 
-pub fn insert<'a>(map: &'a mut CbHashMap<usize, usize>, k: usize, v: usize) -> Option<usize> {
+pub fn insert(map: &mut CbHashMap<usize, usize>, k: usize, v: usize) -> Option<usize> {
     map.insert(k, v)
 }
 
@@ -81,6 +150,72 @@ macro_rules! generate_tests {
             assert_eq!(m.remove(&String::from("hi")), None);
         }
 
+        #[test]
+        fn borrowed_lookups() {
+            let mut strings: $map<String, usize> = $map::new();
+            let mut vecs: $map<Vec<u8>, usize> = $map::new();
+            let mut boxes: $map<Box<str>, usize> = $map::new();
+
+            for i in 0..1000 {
+                strings.insert(i.to_string(), i);
+                vecs.insert(i.to_string().into_bytes(), i);
+                boxes.insert(i.to_string().into_boxed_str(), i);
+            }
+
+            for i in 0..1000 {
+                let s = i.to_string();
+                assert_eq!(strings.get(s.as_str()), Some(&i));
+                assert_eq!(vecs.get(s.as_bytes()), Some(&i));
+                assert_eq!(boxes.get(s.as_str()), Some(&i));
+
+                assert!(strings.contains_key(s.as_str()));
+                *strings.get_mut(s.as_str()).unwrap() += 1;
+                assert_eq!(strings.get(s.as_str()), Some(&(i + 1)));
+            }
+
+            for i in 1000..2000 {
+                assert!(!strings.contains_key(i.to_string().as_str()));
+            }
+
+            for i in 0..1000 {
+                let s = i.to_string();
+                assert_eq!(strings.remove(s.as_str()), Some(i + 1));
+                assert_eq!(vecs.remove(s.as_bytes()), Some(i));
+                assert_eq!(boxes.remove(s.as_str()), Some(i));
+            }
+            assert!(strings.is_empty());
+            assert!(vecs.is_empty());
+            assert!(boxes.is_empty());
+        }
+
+        #[test]
+        fn custom_hasher() {
+            use std::collections::hash_map::RandomState;
+            use std::hash::BuildHasher;
+
+            let hasher = RandomState::new();
+            let mut map = $map::with_hasher(hasher.clone());
+            let mut sized_map = $map::with_capacity_and_hasher(1000, hasher.clone());
+            let mut default_map: $map<usize, usize, RandomState> = Default::default();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+                sized_map.insert(i, i);
+                default_map.insert(i, i);
+            }
+
+            for i in 0..1000 {
+                assert_eq!(map.get(&i), Some(&i));
+                assert_eq!(sized_map.get(&i), Some(&i));
+                assert_eq!(default_map.get(&i), Some(&i));
+            }
+
+            let cloned = map.clone();
+            assert_eq!(cloned.len(), 1000);
+            assert_eq!(cloned.hasher().hash_one(7), hasher.hash_one(7));
+            assert_eq!(map.hasher().hash_one(7), hasher.hash_one(7));
+        }
+
         #[test]
         fn clone() {
             let mut map = $map::new();
@@ -272,3 +407,247 @@ macro_rules! generate_non_alloc_tests {
         }
     };
 }
+
+#[cfg(test)]
+#[macro_export]
+macro_rules! generate_reserve_tests {
+    ($map:ident) => {
+        #[test]
+        fn miss_when_full() {
+            let mut map = $map::with_capacity(16);
+
+            for i in 0..16 {
+                map.insert(i, i);
+            }
+            assert_eq!(map.get(&16), None);
+            assert_eq!(map.remove(&16), None);
+        }
+
+        #[test]
+        fn reserve() {
+            let mut map = $map::new();
+            map.reserve(1000);
+
+            let buckets = map.n_buckets();
+            assert!(buckets > 0);
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+            assert_eq!(map.n_buckets(), buckets);
+
+            // Already have enough room.
+            map.reserve(0);
+            assert_eq!(map.n_buckets(), buckets);
+
+            map.reserve(1000);
+            assert!(map.n_buckets() > buckets);
+            for i in 0..1000 {
+                assert_eq!(map.get(&i), Some(&i));
+            }
+        }
+
+        #[test]
+        fn try_reserve_overflow() {
+            let mut map = $map::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+            let buckets = map.n_buckets();
+
+            assert_eq!(
+                map.try_reserve(usize::MAX),
+                Err($crate::TryReserveError::CapacityOverflow)
+            );
+            assert_eq!(
+                map.try_reserve(usize::MAX / 16),
+                Err($crate::TryReserveError::CapacityOverflow)
+            );
+
+            // The map is unchanged.
+            assert_eq!(map.n_buckets(), buckets);
+            assert_eq!(map.len(), 1000);
+            for i in 0..1000 {
+                assert_eq!(map.get(&i), Some(&i));
+            }
+        }
+
+        #[test]
+        fn shrink() {
+            let mut map = $map::new();
+
+            for i in 0..1000 {
+                map.insert(i.to_string(), i);
+            }
+            for i in 100..1000 {
+                map.remove(&i.to_string());
+            }
+
+            let buckets = map.n_buckets();
+            map.shrink_to(500);
+            assert!(map.n_buckets() < buckets);
+
+            let buckets = map.n_buckets();
+            map.shrink_to(buckets * 2);
+            assert_eq!(map.n_buckets(), buckets);
+
+            map.shrink_to_fit();
+            assert!(map.n_buckets() < buckets);
+            for i in 0..100 {
+                assert_eq!(map.get(&i.to_string()), Some(&i));
+            }
+
+            for i in 0..100 {
+                map.remove(&i.to_string());
+            }
+            map.shrink_to_fit();
+            assert_eq!(map.n_buckets(), 0);
+
+            for i in 0..1000 {
+                map.insert(i.to_string(), i);
+            }
+            assert_eq!(map.len(), 1000);
+        }
+    };
+}
+
+#[cfg(test)]
+#[macro_export]
+macro_rules! generate_stats_tests {
+    ($map:ident) => {
+        #[test]
+        fn stats() {
+            let mut map = $map::new();
+            assert_eq!(map.stats().n_items, 0);
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+            for i in 0..500 {
+                map.remove(&i);
+            }
+
+            let stats = map.stats();
+            assert_eq!(stats.n_buckets, map.n_buckets());
+            assert_eq!(stats.n_items, map.len());
+            assert_eq!(stats.n_occupied, map.n_occupied);
+            assert_eq!(stats.n_occupied, stats.n_items + stats.n_tombstones);
+            assert_eq!(stats.probe_lengths.iter().sum::<usize>(), 500);
+            assert_eq!(stats.group_probe_lengths.iter().sum::<usize>(), 500);
+
+            let n_groups = stats.n_buckets / stats.group_width;
+            let weighted = |h: &[usize]| h.iter().enumerate().map(|(n, c)| n * c).sum::<usize>();
+            for histogram in [
+                &stats.fulls_per_group,
+                &stats.empties_per_group,
+                &stats.tombstones_per_group,
+            ] {
+                assert_eq!(histogram.iter().sum::<usize>(), n_groups);
+            }
+            assert_eq!(weighted(&stats.fulls_per_group), stats.n_items);
+            assert_eq!(weighted(&stats.tombstones_per_group), stats.n_tombstones);
+            assert!(stats.h2_false_positive_rate < 0.02);
+        }
+
+        #[test]
+        fn stats_with_bad_hash() {
+            use std::hash::BuildHasherDefault;
+            use $crate::testing::ConstantHasher;
+
+            let mut map = $map::with_hasher(BuildHasherDefault::<ConstantHasher>::default());
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            // Every key is in the same probe sequence, so each one is a bucket further along.
+            let stats = map.stats();
+            assert_eq!(stats.probe_lengths, vec![1; 100]);
+            assert_eq!(stats.h2_false_positive_rate, 1.0);
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+
+    /// An allocator that keeps track of how many bytes it currently has allocated.
+    #[derive(Clone, Default)]
+    pub struct CountingAllocator(pub Rc<Cell<usize>>);
+
+    unsafe impl Allocator for CountingAllocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let ptr = Global.allocate(layout)?;
+            self.0.set(self.0.get() + layout.size());
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.set(self.0.get() - layout.size());
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    /// A hasher that hashes everything to zero.
+    #[derive(Default)]
+    pub struct ConstantHasher;
+
+    impl std::hash::Hasher for ConstantHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {}
+    }
+
+    /// A hasher that hashes an integer to itself.
+    #[derive(Default)]
+    pub struct IdentityHasher(u64);
+
+    impl std::hash::Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 << 8) | u64::from(b);
+            }
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+
+        fn write_usize(&mut self, i: usize) {
+            self.0 = i as u64;
+        }
+    }
+
+    /// A growth policy that packs the buckets tightly.
+    pub struct Dense;
+
+    impl crate::growth::GrowthPolicy for Dense {
+        const MAX_LOAD_FACTOR: (usize, usize) = (15, 16);
+    }
+
+    /// A growth policy that grows four times bigger, starting smaller than a group.
+    pub struct Quadrupling;
+
+    impl crate::growth::GrowthPolicy for Quadrupling {
+        const MAX_LOAD_FACTOR: (usize, usize) = (3, 4);
+        const GROWTH_FACTOR: usize = 4;
+        const MIN_BUCKETS: usize = 4;
+    }
+
+    /// A growth policy that shrinks the map once most of its items are removed.
+    pub struct Shrinking;
+
+    impl crate::growth::GrowthPolicy for Shrinking {
+        const SHRINK_LOAD_FACTOR: Option<(usize, usize)> = Some((1, 8));
+    }
+}
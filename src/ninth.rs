@@ -0,0 +1,521 @@
+//! A hopscotch hashing map.
+//!
+//! Every item is within `GROUP_SIZE` buckets of its home bucket, and each bucket keeps a bitmap
+//! of which buckets in its neighborhood hold items that call it home. So a lookup only looks at
+//! the buckets in the home bucket's bitmap, which are all close together in memory, plus a
+//! bounded stash.
+//!
+//! An insert takes the first empty bucket after the home bucket. If that's outside the
+//! neighborhood, it hops the empty bucket back towards home by moving items forward into it,
+//! as long as they stay in their own neighborhoods. If it can't, the map grows instead. But if
+//! the map is mostly empty, growing won't help: too many keys share the same home bucket, so
+//! the new key goes in the stash, which holds at most `MAX_STASH` items. If the stash is full
+//! too, the insert panics.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+use crate::group;
+use crate::growth::{self, DefaultGrowth, GrowthPolicy};
+use crate::metadata::{self, Metadata};
+use crate::{capacity_to_buckets, fast_rem, fix_capacity, make_hash, mix_hash, DefaultHashBuilder};
+
+/// Bit `i` is set if bucket `home + i` holds an item whose home is `home`.
+type HopBitmap = u32;
+
+/// The number of buckets in a neighborhood, starting from the home bucket.
+///
+/// This is as wide as the widest group. With 32 buckets, the map fills up to its load factor
+/// before an empty bucket gets stuck outside a neighborhood; 16 buckets only get to about 4/5,
+/// and 8 to about 2/3.
+const GROUP_SIZE: usize = HopBitmap::BITS as usize;
+
+/// The most items that can go in the stash.
+const MAX_STASH: usize = 8;
+
+pub struct Map<K, V, S: BuildHasher = DefaultHashBuilder> {
+    hasher: S,
+    n_items: usize, // Number of live items
+    /// Safety: we maintain the following invariant:
+    /// `self.storage[i]` is initialized whenever `metadata::is_full(self.metadata[i])`.
+    storage: Box<[MaybeUninit<(K, V)>]>,
+    /// Only used to tell full buckets from empty ones, and to skip comparing keys whose `h2`
+    /// doesn't match. There are no tombstones.
+    metadata: Box<[Metadata]>,
+    /// The neighborhood of each home bucket.
+    hops: Box<[HopBitmap]>,
+    /// Items that didn't fit in their neighborhoods while the map was mostly empty, at most
+    /// `MAX_STASH` of them. `n_items` counts these too.
+    stash: Vec<(K, V)>,
+    _ph: PhantomData<(K, V)>,
+}
+
+impl<K, V> Map<K, V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Creates a map that can hold at least `capacity` items without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_buckets_and_hasher(capacity_to_buckets(capacity), hasher)
+    }
+
+    fn with_buckets_and_hasher(n_buckets: usize, hasher: S) -> Self {
+        // A neighborhood can be bigger than the whole map, but then every empty bucket is
+        // already in it, so items never need to hop.
+        let capacity = fix_capacity(n_buckets);
+
+        Self {
+            hasher,
+            n_items: 0,
+            storage: Box::new_uninit_slice(capacity),
+            metadata: vec![metadata::empty(); capacity].into_boxed_slice(),
+            hops: vec![0; capacity].into_boxed_slice(),
+            stash: Vec::new(),
+            _ph: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+}
+
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> Clone for Map<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn clone(&self) -> Self {
+        let mut other = Self::with_buckets_and_hasher(self.n_buckets(), self.hasher.clone());
+        assert_eq!(self.n_buckets(), other.n_buckets());
+
+        for (i, &m) in self.metadata.iter().enumerate() {
+            if metadata::is_full(m) {
+                let (k, v) = unsafe { self.storage[i].assume_init_ref() };
+                other.storage[i].write((k.clone(), v.clone()));
+
+                // Important: Only update the metadata after we successfully clone!
+                // If cloning panics, then updating the metadata before cloning
+                // leads to a read of uninitialized memory when `other` is dropped.
+                other.metadata[i] = m;
+                other.n_items += 1;
+            }
+        }
+        // The bitmaps only point at full buckets, so they can't be copied until every item is.
+        other.hops.copy_from_slice(&self.hops);
+        other.stash = self.stash.clone();
+        other.n_items += self.stash.len();
+
+        other
+    }
+}
+
+unsafe impl<#[may_dangle] K, #[may_dangle] V, S> Drop for Map<K, V, S>
+where
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        if core::mem::needs_drop::<(K, V)>() {
+            for (i, &m) in self.metadata.iter().enumerate() {
+                if metadata::is_full(m) {
+                    unsafe { self.storage[i].assume_init_drop() };
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn len(&self) -> usize {
+        self.n_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_items == 0
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.metadata
+            .iter()
+            .zip(self.storage.iter())
+            .filter(|(m, _)| metadata::is_full(**m))
+            .map(|(_, item)| {
+                // SAFETY: `self.storage[i]` is initialized whenever `self.metadata[i]` is full.
+                let (k, v) = unsafe { item.assume_init_ref() };
+                (k, v)
+            })
+            .chain(self.stash.iter().map(|(k, v)| (k, v)))
+    }
+
+    /// The number of buckets, not counting the stash.
+    #[inline]
+    pub(crate) fn n_buckets(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Move the item in bucket `from`, whose home is `home`, into the empty bucket `to`.
+    fn move_item(&mut self, home: usize, from: usize, to: usize) {
+        let offset = |index: usize| fast_rem(index.wrapping_sub(home), self.n_buckets());
+        self.hops[home] = (self.hops[home] & !(1 << offset(from))) | (1 << offset(to));
+        self.metadata[to] = self.metadata[from];
+        self.metadata[from] = metadata::empty();
+        self.storage.swap(from, to);
+    }
+}
+
+impl<K, V, S> Map<K, V, S>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+{
+    /// Returns the home bucket of `k` and the index of the bucket holding it,
+    /// if it's in its neighborhood.
+    fn probe_find<Q>(&self, k: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.n_buckets() == 0 {
+            return None;
+        }
+
+        let (home, h2) = self.bucket_index_and_h2(k);
+        for offset in group::MaskIter::forward(self.hops[home]) {
+            let index = fast_rem(home + offset, self.n_buckets());
+            if self.metadata[index] == metadata::from_h2(h2) {
+                // SAFETY: the bitmap only points at full buckets.
+                let (kk, _) = unsafe { self.storage[index].assume_init_ref() };
+                if kk.borrow() == k {
+                    return Some((home, index));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the index in the stash of the item with key `k`, if it's there.
+    fn stash_find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.stash.iter().position(|(kk, _)| kk.borrow() == k)
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some((_, index)) = self.probe_find(k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, v) = unsafe { self.storage[index].assume_init_ref() };
+            return Some(v);
+        }
+        self.stash_find(k).map(|index| &self.stash[index].1)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.probe_find(k).is_some() || self.stash_find(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some((_, index)) = self.probe_find(k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, v) = unsafe { self.storage[index].assume_init_mut() };
+            return Some(v);
+        }
+        self.stash_find(k).map(|index| &mut self.stash[index].1)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some((_, index)) = self.probe_find(&k) {
+            // SAFETY: `probe_find` only returns initialized buckets.
+            let (_, vv) = unsafe { self.storage[index].assume_init_mut() };
+            return Some(core::mem::replace(vv, v));
+        }
+        if let Some(index) = self.stash_find(&k) {
+            return Some(core::mem::replace(&mut self.stash[index].1, v));
+        }
+
+        if self.needs_resize() {
+            self.resize();
+        }
+        self.insert_new(k, v);
+        None
+    }
+
+    /// Insert a key that isn't in the map yet, growing the map until there's room for it, or
+    /// stashing it if growing won't help.
+    ///
+    /// Panics if growing won't help and the stash is full.
+    fn insert_new(&mut self, k: K, v: V) {
+        loop {
+            let (home, h2) = self.bucket_index_and_h2(&k);
+            if let Some(index) = self.make_room(home) {
+                let offset = fast_rem(index.wrapping_sub(home), self.n_buckets());
+                self.hops[home] |= 1 << offset;
+                self.metadata[index] = metadata::from_h2(h2);
+                self.storage[index].write((k, v));
+                self.n_items += 1;
+                return;
+            }
+
+            // Growing only helps if the keys are spread out. If the buckets are mostly empty and
+            // there's still no room, too many keys have the same hash.
+            if self.n_items - self.stash.len() < self.n_buckets() / 4 {
+                assert!(
+                    self.stash.len() < MAX_STASH,
+                    "too many keys hash to the same neighborhood"
+                );
+                self.stash.push((k, v));
+                self.n_items += 1;
+                return;
+            }
+            self.resize();
+        }
+    }
+
+    /// Find an empty bucket in the neighborhood of `home`, hopping the nearest empty bucket
+    /// back into it if needed. Returns `None` if there's no room.
+    fn make_room(&mut self, home: usize) -> Option<usize> {
+        let n_buckets = self.n_buckets();
+        let mut distance = (0..n_buckets)
+            .find(|&d| metadata::is_empty(self.metadata[fast_rem(home + d, n_buckets)]))?;
+        let mut empty = fast_rem(home + distance, n_buckets);
+
+        while distance >= GROUP_SIZE {
+            let from = self.hop_back(empty)?;
+            distance -= fast_rem(empty.wrapping_sub(from), n_buckets);
+            empty = from;
+        }
+        Some(empty)
+    }
+
+    /// Move an item from one of the `GROUP_SIZE - 1` buckets before the empty bucket `empty`
+    /// into it, keeping the item in its own neighborhood. Returns the bucket it came from,
+    /// which is now empty, or `None` if no item can move.
+    ///
+    /// This looks at the farthest home buckets first, so that the empty bucket hops back
+    /// as far as it can.
+    fn hop_back(&mut self, empty: usize) -> Option<usize> {
+        for back in (1..GROUP_SIZE).rev() {
+            let home = fast_rem(empty.wrapping_sub(back), self.n_buckets());
+            // Only items before `empty` can move forward into it.
+            let movable = self.hops[home] & ((1 << back) - 1);
+            if let Some(offset) = group::find_first(movable) {
+                let from = fast_rem(home + offset, self.n_buckets());
+                self.move_item(home, from, empty);
+                return Some(from);
+            }
+        }
+        None
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let Some((home, index)) = self.probe_find(k) else {
+            let index = self.stash_find(k)?;
+            self.n_items -= 1;
+            return Some(self.stash.swap_remove(index).1);
+        };
+        let old_bucket = core::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
+        // SAFETY: `probe_find` only returns initialized buckets.
+        let (_, vv) = unsafe { old_bucket.assume_init() };
+        // Lookups only follow the bitmaps, so this can always go back to empty.
+        let offset = fast_rem(index.wrapping_sub(home), self.n_buckets());
+        self.hops[home] &= !(1 << offset);
+        self.metadata[index] = metadata::empty();
+        self.n_items -= 1;
+        Some(vv)
+    }
+
+    fn bucket_index_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> (usize, u8) {
+        let hash = mix_hash(make_hash(&self.hasher, k));
+        let (h1, h2) = (hash >> 7, (hash & 0x7F) as u8);
+        let index = fast_rem(h1 as usize, self.n_buckets());
+        (index, h2)
+    }
+
+    fn needs_resize(&self) -> bool {
+        // Using a load factor of 7/8.
+        self.n_items >= growth::bucket_capacity::<DefaultGrowth>(self.n_buckets())
+    }
+
+    fn resize(&mut self) {
+        // Calculate the new capacity.
+        let capacity = match self.n_buckets() {
+            0 => DefaultGrowth::MIN_BUCKETS,
+            x => growth::grow::<DefaultGrowth>(x),
+        };
+
+        // Set `self.storage` to a new array.
+        let new_storage = Box::new_uninit_slice(capacity);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
+        let old_buckets = Vec::from(old_storage).into_iter();
+
+        // Here, we need to keep the old metadata, as it's unsafe to blindly access the old storage
+        // array.
+        let new_metadata = vec![metadata::empty(); capacity].into_boxed_slice();
+        let old_metadata = core::mem::replace(&mut self.metadata, new_metadata);
+        self.hops = vec![0; capacity].into_boxed_slice();
+        let old_stash = core::mem::take(&mut self.stash);
+
+        self.n_items = 0;
+
+        // Move items from `old_storage` to `self.storage`.
+        for (&metadata, bucket) in old_metadata.iter().zip(old_buckets) {
+            if metadata::is_full(metadata) {
+                // SAFETY: we just checked the invariant above.
+                let (k, v) = unsafe { bucket.assume_init() };
+                self.insert_new(k, v);
+            }
+        }
+        for (k, v) in old_stash {
+            self.insert_new(k, v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ninth::Map;
+    crate::generate_tests!(Map, false);
+    crate::generate_non_alloc_tests!(Map);
+
+    #[test]
+    fn items_stay_in_their_neighborhoods() {
+        let mut map = Map::new();
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        for i in (0..10_000).step_by(3) {
+            map.remove(&i);
+        }
+
+        let n_buckets = map.n_buckets();
+        let mut n_hops = 0;
+        for home in 0..n_buckets {
+            for offset in 0..super::GROUP_SIZE {
+                if map.hops[home] & (1 << offset) != 0 {
+                    let index = (home + offset) % n_buckets;
+                    let (k, _) = unsafe { map.storage[index].assume_init_ref() };
+                    assert_eq!(map.bucket_index_and_h2(k).0, home);
+                    n_hops += 1;
+                }
+            }
+        }
+        assert_eq!(n_hops, map.len());
+    }
+
+    #[test]
+    fn fills_to_load_factor() {
+        // At 7/8 full, plenty of empty buckets start out past the neighborhood.
+        let mut map = Map::with_capacity(1 << 12);
+        let buckets = map.n_buckets();
+        let capacity = crate::growth::bucket_capacity::<crate::growth::DefaultGrowth>(buckets);
+        for i in 0..capacity {
+            map.insert(i, i);
+        }
+        assert_eq!(map.n_buckets(), buckets);
+        for i in 0..capacity {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn bad_hash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::ConstantHasher;
+
+        // Every key has the same home bucket, whose neighborhood holds `GROUP_SIZE` of them, and
+        // the rest go in the stash.
+        let n = super::GROUP_SIZE + super::MAX_STASH;
+        let mut map: Map<usize, usize, BuildHasherDefault<ConstantHasher>> = Map::default();
+        for i in 0..n {
+            map.insert(i, i);
+            assert!(map.stash.len() <= super::MAX_STASH);
+        }
+        assert_eq!(map.stash.len(), super::MAX_STASH);
+        assert_eq!(map.len(), n);
+        assert_eq!(map.iter().count(), n);
+        for i in 0..n {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        for i in (0..n).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.len(), n / 2);
+        assert_eq!(map.clone().iter().count(), n / 2);
+        for i in 0..n {
+            assert_eq!(map.contains_key(&i), i % 2 == 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "too many keys hash to the same neighborhood")]
+    fn bad_hash_overflows_stash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::ConstantHasher;
+
+        let mut map: Map<usize, usize, BuildHasherDefault<ConstantHasher>> = Map::default();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+    }
+
+    #[test]
+    fn identity_hash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::IdentityHasher;
+
+        let mut map: Map<u64, u64, BuildHasherDefault<IdentityHasher>> = Map::default();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        // Mixing the hash spreads the keys out, so none of them need the stash.
+        assert!(map.stash.is_empty());
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+}
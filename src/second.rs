@@ -1,5 +1,8 @@
 //! A naive map with open addressing and quadratic probing.
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
 use core::hash::{BuildHasher, Hash};
 
 use crate::{fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
@@ -56,26 +59,41 @@ impl<K, V> Map<K, V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         let capacity = fix_capacity(capacity);
 
         let storage = (0..capacity).map(|_| Bucket::Empty).collect();
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             n_items: 0,
             n_occupied: 0,
             storage,
         }
     }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher(S::default())
     }
 }
 
-impl<K, V> Map<K, V> {
+impl<K, V, S: BuildHasher> Map<K, V, S> {
     pub fn len(&self) -> usize {
         self.n_items
     }
@@ -84,18 +102,28 @@ impl<K, V> Map<K, V> {
         self.n_items == 0
     }
 
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.storage.iter().filter_map(Bucket::as_inner)
+    }
+
     /// Used for testing
     #[inline]
-    fn n_buckets(&self) -> usize {
+    pub(crate) fn n_buckets(&self) -> usize {
         self.storage.len()
     }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S> Map<K, V, S>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
 {
-    fn probe_find(&self, k: &K) -> ProbeResult {
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let mut current = self.bucket_index(k);
 
         for step in 0..self.n_buckets() {
@@ -103,7 +131,7 @@ where
 
             match &self.storage[current] {
                 Bucket::Empty => return ProbeResult::Empty(current),
-                Bucket::Full(kk, _) if kk == k => {
+                Bucket::Full(kk, _) if kk.borrow() == k => {
                     return ProbeResult::Full(current);
                 }
                 // Keep probing.
@@ -118,14 +146,30 @@ where
         }
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(_) => None,
             ProbeResult::Full(index) => self.storage[index].as_inner().map(|(_, v)| v),
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(_) => None,
             ProbeResult::Full(index) => self.storage[index].as_mut().map(|(_, v)| v),
@@ -149,16 +193,20 @@ where
             }
             ProbeResult::Full(index) => {
                 let (_, vv) = self.storage[index].as_mut().unwrap();
-                Some(std::mem::replace(vv, v))
+                Some(core::mem::replace(vv, v))
             }
         }
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(_) => None,
             ProbeResult::Full(index) => {
-                let old_bucket = std::mem::replace(&mut self.storage[index], Bucket::Tombstone);
+                let old_bucket = core::mem::replace(&mut self.storage[index], Bucket::Tombstone);
                 // Important to decrement only `n_items` and not `n_occupied` here,
                 // since we're leaving a tombstone.
                 self.n_items -= 1;
@@ -167,7 +215,7 @@ where
         }
     }
 
-    fn bucket_index(&self, k: &K) -> usize {
+    fn bucket_index<Q: ?Sized + Hash>(&self, k: &Q) -> usize {
         let hash = make_hash(&self.hasher, k);
         fast_rem(hash as usize, self.n_buckets())
     }
@@ -187,7 +235,7 @@ where
 
         // Set `self.storage` to a new array.
         let new_storage = (0..capacity).map(|_| Bucket::Empty).collect();
-        let old_storage = std::mem::replace(&mut self.storage, new_storage);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
 
         self.n_items = 0;
         self.n_occupied = 0;
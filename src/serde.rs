@@ -0,0 +1,274 @@
+//! `Serialize` and `Deserialize` impls for the maps and sets, behind the `serde` feature.
+//!
+//! Maps are serialized as maps and sets as sequences, same as the std collections.
+
+use alloc::alloc::Allocator;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+
+use ::serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use ::serde::ser::{Serialize, Serializer};
+
+/// Number of items to allocate room for up front, for a collection with `hint` items.
+///
+/// The hint comes from the input, so we don't trust it past about 1 MiB of items
+/// (same cap as serde uses for the std collections). The map still grows normally past that.
+fn capacity_for_hint<T>(hint: Option<usize>) -> usize {
+    const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+    let max_items = MAX_PREALLOC_BYTES / core::mem::size_of::<T>().max(1);
+    hint.unwrap_or(0).min(max_items)
+}
+
+/// Implements `Serialize` and `Deserialize` for one of the maps.
+/// `$new` builds an empty map with room for a number of items, given the hasher and allocator types.
+macro_rules! impl_serde_for_map {
+    ($map:ty, [$($alloc:ident)?], $new:expr) => {
+        impl<K, V, S $(, $alloc)?> Serialize for $map
+        where
+            K: Serialize,
+            V: Serialize,
+            S: BuildHasher,
+            $($alloc: Allocator + Clone,)?
+        {
+            fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+                serializer.collect_map(self.iter())
+            }
+        }
+
+        impl<'de, K, V, S $(, $alloc)?> Deserialize<'de> for $map
+        where
+            K: Deserialize<'de> + Eq + Hash,
+            V: Deserialize<'de>,
+            S: BuildHasher + Default,
+            $($alloc: Allocator + Clone + Default,)?
+        {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct MapVisitor<K, V, S $(, $alloc)?> {
+                    _ph: PhantomData<fn() -> (K, V, S $(, $alloc)?)>,
+                }
+
+                impl<'de, K, V, S $(, $alloc)?> Visitor<'de> for MapVisitor<K, V, S $(, $alloc)?>
+                where
+                    K: Deserialize<'de> + Eq + Hash,
+                    V: Deserialize<'de>,
+                    S: BuildHasher + Default,
+                    $($alloc: Allocator + Clone + Default,)?
+                {
+                    type Value = $map;
+
+                    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        f.write_str("a map")
+                    }
+
+                    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+                        let capacity = capacity_for_hint::<(K, V)>(access.size_hint());
+                        let mut map: $map = $new(capacity);
+
+                        while let Some((k, v)) = access.next_entry()? {
+                            map.insert(k, v);
+                        }
+                        Ok(map)
+                    }
+                }
+
+                deserializer.deserialize_map(MapVisitor { _ph: PhantomData })
+            }
+        }
+    };
+}
+
+impl_serde_for_map!(crate::first::Map<K, V, S>, [], |capacity| {
+    crate::first::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::second::Map<K, V, S>, [], |capacity| {
+    crate::second::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::third::Map<K, V, S>, [], |capacity| {
+    crate::third::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::fourth::Map<K, V, S>, [], |capacity| {
+    crate::fourth::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::fifth::Map<K, V, S, A>, [A], |capacity| {
+    crate::fifth::Map::with_capacity_and_hasher_in(capacity, S::default(), A::default())
+});
+impl_serde_for_map!(crate::sixth::Map<K, V, S, A>, [A], |capacity| {
+    crate::sixth::Map::with_capacity_and_hasher_in(capacity, S::default(), A::default())
+});
+impl_serde_for_map!(crate::seventh::Map<K, V, S>, [], |capacity| {
+    crate::seventh::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::eighth::Map<K, V, S>, [], |capacity| {
+    crate::eighth::Map::with_capacity_and_hasher(capacity, S::default())
+});
+impl_serde_for_map!(crate::ninth::Map<K, V, S>, [], |capacity| {
+    crate::ninth::Map::with_capacity_and_hasher(capacity, S::default())
+});
+
+impl<T, S> Serialize for crate::set::Set<T, S>
+where
+    T: Serialize,
+    S: BuildHasher,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T, S> Deserialize<'de> for crate::set::Set<T, S>
+where
+    T: Deserialize<'de> + Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SetVisitor<T, S> {
+            _ph: PhantomData<fn() -> (T, S)>,
+        }
+
+        impl<'de, T, S> Visitor<'de> for SetVisitor<T, S>
+        where
+            T: Deserialize<'de> + Eq + Hash,
+            S: BuildHasher + Default,
+        {
+            type Value = crate::set::Set<T, S>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a sequence")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let capacity = capacity_for_hint::<T>(access.size_hint());
+                let mut set = crate::set::Set::with_capacity_and_hasher(capacity, S::default());
+
+                while let Some(value) = access.next_element()? {
+                    set.insert(value);
+                }
+                Ok(set)
+            }
+        }
+
+        deserializer.deserialize_seq(SetVisitor { _ph: PhantomData })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ::serde::de::value::{Error, MapDeserializer};
+    use ::serde::Deserialize;
+
+    use crate::DefaultHashBuilder;
+
+    macro_rules! round_trip {
+        ($name:ident, $map:ty) => {
+            #[test]
+            fn $name() {
+                let mut map = <$map>::new();
+                for i in 0..1000 {
+                    map.insert(i.to_string(), i);
+                }
+
+                let json = serde_json::to_string(&map).unwrap();
+                let other: $map = serde_json::from_str(&json).unwrap();
+                assert_eq!(other.len(), 1000);
+                for i in 0..1000 {
+                    assert_eq!(other.get(i.to_string().as_str()), Some(&i));
+                }
+
+                let empty: $map = serde_json::from_str("{}").unwrap();
+                assert!(empty.is_empty());
+                assert!(serde_json::from_str::<$map>("[1, 2]").is_err());
+            }
+        };
+    }
+
+    round_trip!(first, crate::first::Map<String, usize>);
+    round_trip!(second, crate::second::Map<String, usize>);
+    round_trip!(third, crate::third::Map<String, usize>);
+    round_trip!(fourth, crate::fourth::Map<String, usize>);
+    round_trip!(fifth, crate::fifth::Map<String, usize>);
+    round_trip!(sixth, crate::sixth::Map<String, usize>);
+    round_trip!(seventh, crate::seventh::Map<String, usize>);
+    round_trip!(eighth, crate::eighth::Map<String, usize>);
+    round_trip!(ninth, crate::ninth::Map<String, usize>);
+
+    #[test]
+    fn set() {
+        let set: crate::CbHashSet<usize> = (0..1000).collect();
+
+        let json = serde_json::to_string(&set).unwrap();
+        let other: crate::CbHashSet<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(set, other);
+
+        assert_eq!(
+            serde_json::to_string(&crate::CbHashSet::from([7])).unwrap(),
+            "[7]"
+        );
+    }
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    /// An allocator that counts how many times it's been called. Only used by `presized`.
+    #[derive(Clone, Default)]
+    struct CountingAllocs;
+
+    unsafe impl Allocator for CountingAllocs {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn presized() {
+        type Fifth = crate::fifth::Map<usize, usize, DefaultHashBuilder, CountingAllocs>;
+        type Sixth = crate::sixth::Map<usize, usize, DefaultHashBuilder, CountingAllocs>;
+
+        let entries = || MapDeserializer::<_, Error>::new((0..1000).map(|i| (i, i)));
+
+        // `fifth` allocates the storage and metadata separately.
+        ALLOCATIONS.store(0, Ordering::Relaxed);
+        let map = Fifth::deserialize(entries()).unwrap();
+        assert_eq!(map.len(), 1000);
+        assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 2);
+
+        ALLOCATIONS.store(0, Ordering::Relaxed);
+        let map = Sixth::deserialize(entries()).unwrap();
+        assert_eq!(map.len(), 1000);
+        assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 1);
+    }
+
+    /// Checks that deserializing 1000 entries allocates `$buckets` buckets up front,
+    /// and never resizes.
+    macro_rules! presized_buckets {
+        ($name:ident, $map:ty, $buckets:expr) => {
+            #[test]
+            fn $name() {
+                let entries = MapDeserializer::<_, Error>::new((0..1000).map(|i| (i, i)));
+                let map = <$map>::deserialize(entries).unwrap();
+                assert_eq!(map.len(), 1000);
+                assert_eq!(map.n_buckets(), $buckets);
+            }
+        };
+    }
+
+    // The first few maps only grow once they're full, so 1000 items fit in 1024 buckets.
+    presized_buckets!(presized_first, crate::first::Map<usize, usize>, 1024);
+    presized_buckets!(presized_second, crate::second::Map<usize, usize>, 1024);
+    presized_buckets!(presized_third, crate::third::Map<usize, usize>, 1024);
+    presized_buckets!(presized_fourth, crate::fourth::Map<usize, usize>, 1024);
+    // The rest grow at 7/8 full, which is 896 items in 1024 buckets.
+    presized_buckets!(presized_fifth, crate::fifth::Map<usize, usize>, 2048);
+    presized_buckets!(presized_seventh, crate::seventh::Map<usize, usize>, 2048);
+    presized_buckets!(presized_eighth, crate::eighth::Map<usize, usize>, 2048);
+    presized_buckets!(presized_ninth, crate::ninth::Map<usize, usize>, 2048);
+}
@@ -0,0 +1,703 @@
+//! A hash set built on top of the [`fifth`](crate::fifth) map.
+
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+use core::iter::{Chain, FusedIterator};
+use core::ops::{BitAnd, BitOr, BitXor, Sub};
+
+use crate::fifth::{self, Map};
+use crate::DefaultHashBuilder;
+
+pub struct Set<T, S: BuildHasher = DefaultHashBuilder> {
+    map: Map<T, (), S>,
+}
+
+impl<T> Set<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<T, S: BuildHasher> Set<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            map: Map::with_capacity_and_hasher(capacity, hasher),
+        }
+    }
+
+    /// Returns a reference to the set's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+}
+
+impl<T, S: BuildHasher + Default> Default for Set<T, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<T: Clone, S: BuildHasher + Clone> Clone for Set<T, S> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<T, S: BuildHasher> Set<T, S> {
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of items the set can hold without resizing.
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// Clears the set, removing all values. Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// An iterator visiting all values in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.map.keys(),
+        }
+    }
+
+    /// Retains only the values specified by the predicate.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|k, _| f(k))
+    }
+
+    /// Clears the set, returning all values as an iterator.
+    pub fn drain(&mut self) -> Drain<'_, T, S> {
+        Drain {
+            inner: self.map.drain(),
+        }
+    }
+}
+
+impl<T, S> Set<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Adds a value to the set. Returns whether the value was newly inserted.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    /// Adds a value to the set, replacing the existing equal value, if any.
+    /// Returns the replaced value.
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        match self.map.get_key_value_mut(&value) {
+            Some((k, _)) => Some(core::mem::replace(k, value)),
+            None => {
+                self.map.insert(value, ());
+                None
+            }
+        }
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Returns a reference to the value in the set that is equal to the given value.
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.get_key_value(value).map(|(k, _)| k)
+    }
+
+    /// Removes a value from the set. Returns whether the value was present.
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Removes and returns the value in the set that is equal to the given value.
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove_entry(value).map(|(k, _)| k)
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit()
+    }
+
+    /// Visits the values that are in `self` or `other`, without duplicates.
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, S> {
+        // Iterate over the bigger set in full, so fewer lookups are needed.
+        let (larger, smaller) = if self.len() >= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Union {
+            inner: larger.iter().chain(smaller.difference(larger)),
+        }
+    }
+
+    /// Visits the values that are in both `self` and `other`.
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T, S> {
+        // Iterate over the smaller set, looking each value up in the bigger one.
+        let (smaller, larger) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection {
+            iter: smaller.iter(),
+            other: larger,
+        }
+    }
+
+    /// Visits the values that are in `self` but not in `other`.
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T, S> {
+        Difference {
+            iter: self.iter(),
+            other,
+        }
+    }
+
+    /// Visits the values that are in `self` or `other`, but not in both.
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T, S> {
+        SymmetricDifference {
+            inner: self.difference(other).chain(other.difference(self)),
+        }
+    }
+
+    /// Returns `true` if every value in `self` is also in `other`.
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.iter().all(|v| other.contains(v))
+    }
+
+    /// Returns `true` if every value in `other` is also in `self`.
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Returns `true` if `self` and `other` have no values in common.
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+impl<T, S> PartialEq for Set<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+impl<T, S> Eq for Set<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+}
+
+impl<T: fmt::Debug, S: BuildHasher> fmt::Debug for Set<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T, S> FromIterator<T> for Set<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::with_hasher(S::default());
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T, S> Extend<T> for Set<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|v| (v, ())));
+    }
+}
+
+impl<'a, T, S> Extend<&'a T> for Set<T, S>
+where
+    T: Eq + Hash + Copy,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for Set<T>
+where
+    T: Eq + Hash,
+{
+    fn from(arr: [T; N]) -> Self {
+        Self::from_iter(arr)
+    }
+}
+
+impl<T, S> BitOr<&Set<T, S>> for &Set<T, S>
+where
+    T: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = Set<T, S>;
+
+    /// Returns the union of `self` and `rhs` as a new set.
+    fn bitor(self, rhs: &Set<T, S>) -> Set<T, S> {
+        self.union(rhs).cloned().collect()
+    }
+}
+
+impl<T, S> BitAnd<&Set<T, S>> for &Set<T, S>
+where
+    T: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = Set<T, S>;
+
+    /// Returns the intersection of `self` and `rhs` as a new set.
+    fn bitand(self, rhs: &Set<T, S>) -> Set<T, S> {
+        self.intersection(rhs).cloned().collect()
+    }
+}
+
+impl<T, S> BitXor<&Set<T, S>> for &Set<T, S>
+where
+    T: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = Set<T, S>;
+
+    /// Returns the symmetric difference of `self` and `rhs` as a new set.
+    fn bitxor(self, rhs: &Set<T, S>) -> Set<T, S> {
+        self.symmetric_difference(rhs).cloned().collect()
+    }
+}
+
+impl<T, S> Sub<&Set<T, S>> for &Set<T, S>
+where
+    T: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = Set<T, S>;
+
+    /// Returns the difference of `self` and `rhs` as a new set.
+    fn sub(self, rhs: &Set<T, S>) -> Set<T, S> {
+        self.difference(rhs).cloned().collect()
+    }
+}
+
+/// An iterator over the values of a [`Set`].
+pub struct Iter<'a, T> {
+    inner: fifth::Keys<'a, T, ()>,
+}
+
+impl<T> Clone for Iter<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+impl<T> FusedIterator for Iter<'_, T> {}
+
+/// An owning iterator over the values of a [`Set`].
+pub struct IntoIter<T, S: BuildHasher = DefaultHashBuilder> {
+    inner: fifth::IntoKeys<T, (), S>,
+}
+
+impl<T, S: BuildHasher> Iterator for IntoIter<T, S> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T, S: BuildHasher> ExactSizeIterator for IntoIter<T, S> {}
+impl<T, S: BuildHasher> FusedIterator for IntoIter<T, S> {}
+
+/// A draining iterator over the values of a [`Set`].
+pub struct Drain<'a, T, S: BuildHasher = DefaultHashBuilder> {
+    inner: fifth::Drain<'a, T, (), S>,
+}
+
+impl<T, S: BuildHasher> Iterator for Drain<'_, T, S> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T, S: BuildHasher> ExactSizeIterator for Drain<'_, T, S> {}
+impl<T, S: BuildHasher> FusedIterator for Drain<'_, T, S> {}
+
+/// A lazy iterator over the union of two [`Set`]s.
+pub struct Union<'a, T, S: BuildHasher = DefaultHashBuilder> {
+    inner: Chain<Iter<'a, T>, Difference<'a, T, S>>,
+}
+
+impl<'a, T, S> Iterator for Union<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> FusedIterator for Union<'_, T, S> {}
+
+/// A lazy iterator over the intersection of two [`Set`]s.
+pub struct Intersection<'a, T, S: BuildHasher = DefaultHashBuilder> {
+    iter: Iter<'a, T>,
+    other: &'a Set<T, S>,
+}
+
+impl<'a, T, S> Iterator for Intersection<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter.find(|v| other.contains(v))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> FusedIterator for Intersection<'_, T, S> {}
+
+/// A lazy iterator over the difference of two [`Set`]s.
+pub struct Difference<'a, T, S: BuildHasher = DefaultHashBuilder> {
+    iter: Iter<'a, T>,
+    other: &'a Set<T, S>,
+}
+
+impl<'a, T, S> Iterator for Difference<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter.find(|v| !other.contains(v))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> FusedIterator for Difference<'_, T, S> {}
+
+/// A lazy iterator over the symmetric difference of two [`Set`]s.
+pub struct SymmetricDifference<'a, T, S: BuildHasher = DefaultHashBuilder> {
+    inner: Chain<Difference<'a, T, S>, Difference<'a, T, S>>,
+}
+
+impl<'a, T, S> Iterator for SymmetricDifference<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> FusedIterator for SymmetricDifference<'_, T, S> {}
+
+impl<T, S: BuildHasher> IntoIterator for Set<T, S> {
+    type Item = T;
+    type IntoIter = IntoIter<T, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.map.into_keys(),
+        }
+    }
+}
+
+impl<'a, T, S: BuildHasher> IntoIterator for &'a Set<T, S> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::set::Set;
+
+    fn sorted<'a>(iter: impl Iterator<Item = &'a usize>) -> Vec<usize> {
+        let mut v: Vec<_> = iter.copied().collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn insert_contains_remove() {
+        let mut set = Set::new();
+
+        for i in 0..1000 {
+            assert!(set.insert(i));
+            assert!(!set.insert(i));
+        }
+        assert_eq!(set.len(), 1000);
+
+        for i in 0..1000 {
+            assert!(set.contains(&i));
+            assert_eq!(set.get(&i), Some(&i));
+        }
+        assert!(!set.contains(&1000));
+
+        for i in 0..500 {
+            assert!(set.remove(&i));
+            assert!(!set.remove(&i));
+        }
+        for i in 500..1000 {
+            assert_eq!(set.take(&i), Some(i));
+            assert_eq!(set.take(&i), None);
+        }
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let mut set: Set<String> = Set::new();
+
+        for i in 0..1000 {
+            set.insert(i.to_string());
+        }
+        for i in 0..1000 {
+            let s = i.to_string();
+            assert!(set.contains(s.as_str()));
+            assert_eq!(set.get(s.as_str()), Some(&s));
+        }
+        assert_eq!(set.take("7"), Some(String::from("7")));
+        assert!(!set.remove("7"));
+    }
+
+    #[test]
+    fn replace() {
+        #[derive(Debug)]
+        struct Tagged(usize, &'static str);
+
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Tagged {}
+        impl std::hash::Hash for Tagged {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state)
+            }
+        }
+
+        let mut set = Set::new();
+        assert!(set.replace(Tagged(1, "old")).is_none());
+        let old = set.replace(Tagged(1, "new")).unwrap();
+        assert_eq!(old.1, "old");
+        assert_eq!(set.get(&Tagged(1, "")).unwrap().1, "new");
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn iter_and_drain() {
+        let mut set: Set<usize> = (0..1000).collect();
+        assert_eq!(set.iter().len(), 1000);
+        assert_eq!(sorted(set.iter()), (0..1000).collect::<Vec<_>>());
+
+        set.retain(|v| v % 2 == 0);
+        assert_eq!(set.len(), 500);
+
+        let mut drained: Vec<_> = set.drain().collect();
+        drained.sort();
+        assert_eq!(drained, (0..1000).step_by(2).collect::<Vec<_>>());
+        assert!(set.is_empty());
+
+        set.extend([1, 2, 3]);
+        let mut owned: Vec<_> = set.into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn set_operations() {
+        let a: Set<usize> = (0..100).collect();
+        let b: Set<usize> = (50..200).collect();
+
+        assert_eq!(sorted(a.union(&b)), (0..200).collect::<Vec<_>>());
+        assert_eq!(sorted(b.union(&a)), (0..200).collect::<Vec<_>>());
+        assert_eq!(sorted(a.intersection(&b)), (50..100).collect::<Vec<_>>());
+        assert_eq!(sorted(b.intersection(&a)), (50..100).collect::<Vec<_>>());
+        assert_eq!(sorted(a.difference(&b)), (0..50).collect::<Vec<_>>());
+        assert_eq!(sorted(b.difference(&a)), (100..200).collect::<Vec<_>>());
+        assert_eq!(
+            sorted(a.symmetric_difference(&b)),
+            (0..50).chain(100..200).collect::<Vec<_>>()
+        );
+
+        assert_eq!(&a | &b, (0..200).collect());
+        assert_eq!(&a & &b, (50..100).collect());
+        assert_eq!(&a - &b, (0..50).collect());
+        assert_eq!(&a ^ &b, (0..50).chain(100..200).collect());
+    }
+
+    #[test]
+    fn subsets() {
+        let a = Set::from([1, 2, 3]);
+        let b = Set::from([1, 2, 3, 4]);
+        let c = Set::from([5, 6]);
+        let empty = Set::new();
+
+        assert!(a.is_subset(&b));
+        assert!(!b.is_subset(&a));
+        assert!(b.is_superset(&a));
+        assert!(!a.is_superset(&b));
+        assert!(a.is_subset(&a));
+        assert!(empty.is_subset(&a));
+
+        assert!(a.is_disjoint(&c));
+        assert!(!a.is_disjoint(&b));
+        assert!(empty.is_disjoint(&empty));
+    }
+
+    #[test]
+    fn equality_and_debug() {
+        let a = Set::from([1, 2, 3]);
+        let mut b = a.clone();
+        assert_eq!(a, b);
+
+        b.insert(4);
+        assert_ne!(a, b);
+        b.remove(&4);
+        assert_eq!(a, b);
+
+        assert_eq!(format!("{:?}", Set::from([1])), "{1}");
+        assert_eq!(format!("{:?}", Set::<usize>::new()), "{}");
+    }
+
+    #[test]
+    fn remove_and_reinsert() {
+        let mut set = Set::new();
+        set.extend(0..1000);
+
+        let buckets = set.map.n_buckets();
+        for i in 0..1000 {
+            assert!(set.remove(&i));
+        }
+        set.extend(&(0..1000).collect::<Vec<_>>());
+        assert_eq!(set.len(), 1000);
+        assert_eq!(set.map.n_buckets(), buckets);
+    }
+}
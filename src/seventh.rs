@@ -0,0 +1,432 @@
+//! A Robin Hood hashing map.
+//!
+//! This uses linear probing, but keeps each run of full buckets sorted by home bucket, so that
+//! an item that is far from its home bucket takes the bucket of one that is closer to its own.
+//! That keeps the probe lengths even, lets a lookup stop as soon as it passes where the key
+//! would be, and lets removal shift the rest of the run back instead of leaving tombstones.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+use crate::growth::{self, DefaultGrowth, GrowthPolicy};
+use crate::{capacity_to_buckets, fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
+
+/// Marks an empty bucket in `Map::hashes`.
+const EMPTY: u64 = 0;
+
+/// Set on every stored hash, so that it's never `EMPTY`.
+const FULL_BIT: u64 = 1 << 63;
+
+enum ProbeResult {
+    /// The key isn't in the map. It belongs in the bucket at this index, which is either empty
+    /// or holds an item that is closer to its home bucket. Also returns the hash.
+    Vacant(usize, u64),
+    Full(usize),
+}
+
+pub struct Map<K, V, S: BuildHasher = DefaultHashBuilder> {
+    hasher: S,
+    n_items: usize, // Number of live items
+    /// The hash of the item in each bucket, or `EMPTY`.
+    ///
+    /// Keeping the whole hash means that a lookup only compares keys whose hashes match,
+    /// and that resizing doesn't need to hash the keys again.
+    ///
+    /// Safety: we maintain the following invariant:
+    /// `self.storage[i]` is initialized whenever `self.hashes[i] != EMPTY`.
+    hashes: Box<[u64]>,
+    storage: Box<[MaybeUninit<(K, V)>]>,
+    _ph: PhantomData<(K, V)>,
+}
+
+impl<K, V> Map<K, V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Creates a map that can hold at least `capacity` items without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_buckets_and_hasher(capacity_to_buckets(capacity), hasher)
+    }
+
+    fn with_buckets_and_hasher(n_buckets: usize, hasher: S) -> Self {
+        let capacity = fix_capacity(n_buckets);
+
+        Self {
+            hasher,
+            n_items: 0,
+            hashes: vec![EMPTY; capacity].into_boxed_slice(),
+            storage: Box::new_uninit_slice(capacity),
+            _ph: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+}
+
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> Clone for Map<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn clone(&self) -> Self {
+        let mut other = Self::with_buckets_and_hasher(self.n_buckets(), self.hasher.clone());
+        assert_eq!(self.n_buckets(), other.n_buckets());
+
+        for (i, &hash) in self.hashes.iter().enumerate() {
+            if hash != EMPTY {
+                let (k, v) = unsafe { self.storage[i].assume_init_ref() };
+                other.storage[i].write((k.clone(), v.clone()));
+
+                // Important: Only update the hash after we successfully clone!
+                // If cloning panics, then updating the hash before cloning
+                // leads to a read of uninitialized memory when `other` is dropped.
+                other.hashes[i] = hash;
+                other.n_items += 1;
+            }
+        }
+
+        other
+    }
+}
+
+unsafe impl<#[may_dangle] K, #[may_dangle] V, S> Drop for Map<K, V, S>
+where
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        if core::mem::needs_drop::<(K, V)>() {
+            for (i, &hash) in self.hashes.iter().enumerate() {
+                if hash != EMPTY {
+                    unsafe { self.storage[i].assume_init_drop() };
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn len(&self) -> usize {
+        self.n_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_items == 0
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.hashes
+            .iter()
+            .zip(self.storage.iter())
+            .filter(|(&hash, _)| hash != EMPTY)
+            .map(|(_, item)| {
+                // SAFETY: `self.storage[i]` is initialized whenever `self.hashes[i]` isn't empty.
+                let (k, v) = unsafe { item.assume_init_ref() };
+                (k, v)
+            })
+    }
+
+    /// The number of buckets, full or not.
+    #[inline]
+    pub(crate) fn n_buckets(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// How far the item with `hash` in bucket `index` is from its home bucket.
+    #[inline]
+    fn displacement(&self, index: usize, hash: u64) -> usize {
+        let home = fast_rem(hash as usize, self.n_buckets());
+        fast_rem(index.wrapping_sub(home), self.n_buckets())
+    }
+
+    /// Returns the length of the longest probe, which Robin Hood hashing keeps short.
+    pub fn max_displacement(&self) -> usize {
+        self.hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, &hash)| hash != EMPTY)
+            .map(|(i, &hash)| self.displacement(i, hash))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<K, V, S> Map<K, V, S>
+where
+    K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+{
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let hash = make_hash(&self.hasher, k) | FULL_BIT;
+        if self.n_buckets() == 0 {
+            return ProbeResult::Vacant(0, hash);
+        }
+
+        let mut current = fast_rem(hash as usize, self.n_buckets());
+        for distance in 0..self.n_buckets() {
+            let stored = self.hashes[current];
+
+            // The run is sorted by home bucket, so once we reach an item that is closer to its
+            // home than we are to ours, the key can't be any further along.
+            if stored == EMPTY || self.displacement(current, stored) < distance {
+                return ProbeResult::Vacant(current, hash);
+            } else if stored == hash {
+                // SAFETY: we checked the invariant that `stored != EMPTY`.
+                let (kk, _) = unsafe { self.storage[current].assume_init_ref() };
+                if kk.borrow() == k {
+                    return ProbeResult::Full(current);
+                }
+            }
+            current = fast_rem(current + 1, self.n_buckets());
+        }
+
+        unreachable!("backing storage is full, we didn't resize correctly")
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Vacant(..) => None,
+            ProbeResult::Full(index) => {
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (_, v) = unsafe { self.storage[index].assume_init_ref() };
+                Some(v)
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Vacant(..) => None,
+            ProbeResult::Full(index) => {
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (_, v) = unsafe { self.storage[index].assume_init_mut() };
+                Some(v)
+            }
+        }
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if self.needs_resize() {
+            self.resize();
+        }
+
+        match self.probe_find(&k) {
+            ProbeResult::Vacant(index, hash) => {
+                self.insert_at(index, hash, (k, v));
+                None
+            }
+            ProbeResult::Full(index) => {
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (_, vv) = unsafe { self.storage[index].assume_init_mut() };
+                Some(core::mem::replace(vv, v))
+            }
+        }
+    }
+
+    /// Put a new item in bucket `index`, pushing the rest of the run back by one.
+    ///
+    /// Every item that moves gets one bucket further from home, which keeps the run sorted.
+    /// This ends up the same as swapping the new item with each richer item in turn and
+    /// carrying on with the one that was displaced.
+    fn insert_at(&mut self, index: usize, hash: u64, item: (K, V)) {
+        let mut empty = index;
+        while self.hashes[empty] != EMPTY {
+            empty = fast_rem(empty + 1, self.n_buckets());
+        }
+
+        // Walk the empty bucket back to `index`.
+        while empty != index {
+            let previous = fast_rem(empty.wrapping_sub(1), self.n_buckets());
+            self.hashes.swap(empty, previous);
+            self.storage.swap(empty, previous);
+            empty = previous;
+        }
+
+        self.hashes[index] = hash;
+        self.storage[index].write(item);
+        self.n_items += 1;
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Vacant(..) => None,
+            ProbeResult::Full(index) => {
+                let old_bucket =
+                    core::mem::replace(&mut self.storage[index], MaybeUninit::uninit());
+                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
+                let (_, vv) = unsafe { old_bucket.assume_init() };
+                self.hashes[index] = EMPTY;
+                self.n_items -= 1;
+                self.shift_back(index);
+                Some(vv)
+            }
+        }
+    }
+
+    /// Fill the empty bucket at `index` by shifting the rest of its run back by one,
+    /// stopping at an empty bucket or an item that is already in its home bucket.
+    ///
+    /// This is what lets us get away without tombstones.
+    fn shift_back(&mut self, mut index: usize) {
+        loop {
+            let next = fast_rem(index + 1, self.n_buckets());
+            let hash = self.hashes[next];
+            if hash == EMPTY || self.displacement(next, hash) == 0 {
+                break;
+            }
+            self.hashes.swap(index, next);
+            self.storage.swap(index, next);
+            index = next;
+        }
+    }
+
+    fn needs_resize(&self) -> bool {
+        // Using a load factor of 7/8.
+        self.n_items >= growth::bucket_capacity::<DefaultGrowth>(self.n_buckets())
+    }
+
+    fn resize(&mut self) {
+        // Calculate the new capacity.
+        let capacity = match self.n_buckets() {
+            0 => DefaultGrowth::MIN_BUCKETS,
+            x => growth::grow::<DefaultGrowth>(x),
+        };
+
+        // Set `self.storage` to a new array.
+        let new_storage = Box::new_uninit_slice(capacity);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
+        let old_buckets = Vec::from(old_storage).into_iter();
+
+        // Here, we need to keep the old hashes, as it's unsafe to blindly access the old storage
+        // array.
+        let new_hashes = vec![EMPTY; capacity].into_boxed_slice();
+        let old_hashes = core::mem::replace(&mut self.hashes, new_hashes);
+
+        self.n_items = 0;
+
+        // Move items from `old_storage` to `self.storage`. The keys are all different, so we
+        // only need to find where each one goes, without comparing any keys.
+        for (&hash, bucket) in old_hashes.iter().zip(old_buckets) {
+            if hash != EMPTY {
+                // SAFETY: we just checked the invariant above.
+                let item = unsafe { bucket.assume_init() };
+                let mut index = fast_rem(hash as usize, capacity);
+                let mut distance = 0;
+                while self.hashes[index] != EMPTY
+                    && self.displacement(index, self.hashes[index]) >= distance
+                {
+                    index = fast_rem(index + 1, capacity);
+                    distance += 1;
+                }
+                self.insert_at(index, hash, item);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::seventh::Map;
+    // There are no tombstones, so reinserting never resizes.
+    crate::generate_tests!(Map, false);
+    crate::generate_non_alloc_tests!(Map);
+
+    #[test]
+    fn runs_stay_sorted() {
+        let mut map = Map::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).step_by(3) {
+            map.remove(&i);
+        }
+
+        // Every item is at least as far from home as the one before it, unless it's the start
+        // of a new run.
+        let n_buckets = map.n_buckets();
+        for i in 0..n_buckets {
+            let previous = (i + n_buckets - 1) % n_buckets;
+            let (hash, previous_hash) = (map.hashes[i], map.hashes[previous]);
+            if hash != super::EMPTY && map.displacement(i, hash) > 0 {
+                assert_ne!(previous_hash, super::EMPTY);
+                assert!(map.displacement(previous, previous_hash) + 1 >= map.displacement(i, hash));
+            }
+        }
+
+        for i in 0..1000 {
+            let expected = (i % 3 != 0).then_some(&i);
+            assert_eq!(map.get(&i), expected);
+        }
+    }
+
+    #[test]
+    fn bad_hash() {
+        use std::hash::BuildHasherDefault;
+
+        use crate::testing::ConstantHasher;
+
+        let mut map: Map<usize, usize, BuildHasherDefault<ConstantHasher>> = Map::default();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.max_displacement(), 99);
+        for i in (0..100).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.max_displacement(), 49);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), (i % 2 == 1).then_some(&i));
+        }
+        assert_eq!(map.iter().count(), 50);
+    }
+}
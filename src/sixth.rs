@@ -1,257 +1,580 @@
 //! A Swiss Tables-inspired map with metadata.
-//! This uses a ton of unsafe to put the metadata and the storage array in the same allocation.
-//!
-//! Warning: This kinda works, but I still need to add the parallel probing from `fifth`.
-
+//! This uses a ton of unsafe to put the storage array and the metadata in the same allocation,
+//! with the buckets stored backwards right before the metadata (like `hashbrown`).
+//! Otherwise, it probes a group at a time just like `fifth`.
+
+use alloc::alloc::{Allocator, Global, Layout};
+use core::borrow::Borrow;
+use core::clone::TrivialClone;
+use core::fmt;
 use core::hash::{BuildHasher, Hash};
-use std::alloc::{Allocator, Global, Layout};
-use std::mem::MaybeUninit;
-use std::ptr::NonNull;
+use core::hint::{likely, unlikely};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+use core::slice;
 
-use crate::{fix_capacity, make_hash, DefaultHashBuilder};
+use crate::group::{self, Group, TargetGroup};
+use crate::growth::{self, DefaultGrowth, GrowthPolicy};
+use crate::{fast_rem, handle_reserve_error, make_hash, DefaultHashBuilder, TryReserveError};
 
 use crate::metadata::{self, Metadata};
+use crate::stats::{self, MapStats};
 
 pub enum ProbeResult {
     Empty(usize, u8),
     Full(usize),
-    End,
+}
+
+/// The number of metadata bytes for `capacity` buckets, including the mirrored tail group.
+fn metadata_len(capacity: usize) -> Result<usize, TryReserveError> {
+    capacity
+        .checked_add(TargetGroup::WIDTH)
+        .ok_or(TryReserveError::CapacityOverflow)
+}
+
+/// Rounds a nonzero number of buckets up to at least one whole group.
+///
+/// The growth policy's minimum number of buckets may be smaller than a group.
+fn whole_group(n_buckets: usize) -> usize {
+    if n_buckets == 0 {
+        0
+    } else {
+        n_buckets.max(TargetGroup::WIDTH)
+    }
+}
+
+/// The metadata of a map with no buckets, so that it doesn't need to allocate.
+///
+/// A lookup loads this group, sees that everything is empty, and stops. Nothing ever writes to
+/// it, since inserting into a map with no buckets always resizes first.
+static EMPTY_GROUP: [Metadata; TargetGroup::WIDTH] = [metadata::empty(); TargetGroup::WIDTH];
+
+fn empty_metadata() -> NonNull<Metadata> {
+    NonNull::from(&EMPTY_GROUP).cast()
 }
 
 /// Returns a pair `(layout, offset)`, where `offset` is the offset in bytes from the beginning of
-/// the layout to the start of the `storage`.
-fn layout_for_capacity<K, V>(capacity: usize) -> (Layout, usize) {
-    Layout::array::<Metadata>(capacity)
-        .unwrap()
-        .extend(Layout::array::<(K, V)>(capacity).unwrap())
-        .unwrap()
+/// the layout to the start of the metadata.
+///
+/// The buckets come first, so that the last one ends right where the metadata starts.
+fn layout_for_capacity<K, V>(capacity: usize) -> Result<(Layout, usize), TryReserveError> {
+    let storage =
+        Layout::array::<(K, V)>(capacity).map_err(|_| TryReserveError::CapacityOverflow)?;
+    let metadata = Layout::array::<Metadata>(metadata_len(capacity)?)
+        .map_err(|_| TryReserveError::CapacityOverflow)?;
+    storage
+        .extend(metadata)
+        .map_err(|_| TryReserveError::CapacityOverflow)
 }
 
-/// Allocate backing storage with `capacity`.
+/// Allocate backing storage with `capacity`, and return a pointer to the start of the metadata.
 ///
-/// Pretty sure `capacity` needs to be nonzero for this to be sound.
+/// SAFETY: `capacity` must be nonzero.
 unsafe fn allocate_for_capacity<A: Allocator, K, V>(
     allocator: &A,
     capacity: usize,
-) -> (NonNull<Metadata>, NonNull<MaybeUninit<(K, V)>>) {
-    let (layout, start_of_storage) = layout_for_capacity::<K, V>(capacity);
+) -> Result<NonNull<Metadata>, TryReserveError> {
+    let (layout, start_of_metadata) = layout_for_capacity::<K, V>(capacity)?;
 
-    let allocation = if let Ok(ptr) = allocator.allocate(layout) {
-        ptr
-    } else {
-        // Abort the program.
-        std::alloc::handle_alloc_error(layout)
-    };
+    let allocation = allocator
+        .allocate(layout)
+        .map_err(|_| TryReserveError::AllocError { layout })?;
 
-    let metadata = allocation.as_mut_ptr().cast::<Metadata>();
-    let metadata = NonNull::new(metadata).unwrap();
-    let storage = allocation
+    let metadata = allocation
         .as_mut_ptr()
-        .offset(start_of_storage as isize)
-        .cast::<MaybeUninit<(K, V)>>();
-    let storage = NonNull::new(storage).unwrap();
+        .add(start_of_metadata)
+        .cast::<Metadata>();
+    let metadata = NonNull::new(metadata).unwrap();
 
-    // Initialize metadata.
+    // Initialize metadata, including the mirrored tail.
     // We'll leave storage uninitialized.
-    std::ptr::write_bytes(metadata.as_ptr(), metadata::empty(), capacity);
+    core::ptr::write_bytes(
+        metadata.as_ptr(),
+        metadata::empty(),
+        capacity + TargetGroup::WIDTH,
+    );
 
-    (metadata, storage)
+    Ok(metadata)
+}
+
+/// Free backing storage that was allocated by `allocate_for_capacity`.
+///
+/// SAFETY: `metadata` must have come from `allocate_for_capacity` with the same `allocator`
+/// and `capacity`.
+unsafe fn deallocate_for_capacity<A: Allocator, K, V>(
+    allocator: &A,
+    metadata: NonNull<Metadata>,
+    capacity: usize,
+) {
+    // This layout was already used to allocate, so it can't fail.
+    let (layout, start_of_metadata) = layout_for_capacity::<K, V>(capacity).unwrap();
+    allocator.deallocate(metadata.byte_sub(start_of_metadata).cast(), layout);
 }
 
-pub struct Map<K, V, S: BuildHasher = DefaultHashBuilder, A: Allocator + Clone = Global> {
+pub struct Map<
+    K,
+    V,
+    S: BuildHasher = DefaultHashBuilder,
+    A: Allocator + Clone = Global,
+    P: GrowthPolicy = DefaultGrowth,
+> {
     hasher: S,
     allocator: A,
     n_items: usize,    // Number of live items
     n_occupied: usize, // Number of occupied buckets
     n_buckets: usize,  // Number of total buckets
+    /// The buckets are stored right before the metadata, in reverse order, like in
+    /// `hashbrown`. So this is the only pointer we need.
+    ///
     /// SAFETY:
     /// Two invariants:
-    /// - `metadata` and `storage` are non-null iff `n_buckets > 0`.
-    /// - `storage[i]` is initialized if `metadata[i].is_value()`.
+    /// - `metadata` points to `EMPTY_GROUP` iff `n_buckets == 0`.
+    /// - `self.bucket(i)` is initialized if `metadata[i].is_value()`.
+    ///
+    /// `metadata` has an extra `TargetGroup::WIDTH` elements to avoid wrapping SIMD access.
     metadata: NonNull<Metadata>,
-    storage: NonNull<MaybeUninit<(K, V)>>,
-    _ph: std::marker::PhantomData<(K, V)>,
+    _ph: core::marker::PhantomData<(K, V, P)>,
 }
 
-impl<K, V, S, A> Drop for Map<K, V, S, A>
+unsafe impl<#[may_dangle] K, #[may_dangle] V, S, A, P> Drop for Map<K, V, S, A, P>
 where
     S: BuildHasher,
     A: Allocator + Clone,
+    P: GrowthPolicy,
 {
     fn drop(&mut self) {
-        if std::mem::needs_drop::<(K, V)>() {
-            for offset in 0..(self.n_buckets as isize) {
-                unsafe {
-                    let metadata = self.metadata.as_ptr().offset(offset);
-                    let storage = self.storage.as_ptr().offset(offset);
-
-                    if metadata::is_full(*metadata) {
-                        let (_k, _v) = std::ptr::read(storage).assume_init();
-                    }
+        if core::mem::needs_drop::<(K, V)>() {
+            for (i, &m) in self.metadata().iter().take(self.n_buckets).enumerate() {
+                if metadata::is_full(m) {
+                    unsafe { (*self.bucket(i)).assume_init_drop() };
                 }
             }
         }
 
         if self.n_buckets > 0 {
-            let (layout, _) = layout_for_capacity::<K, V>(self.n_buckets);
             unsafe {
-                self.allocator.deallocate(self.metadata.cast(), layout);
+                deallocate_for_capacity::<_, K, V>(&self.allocator, self.metadata, self.n_buckets);
+            }
+        }
+    }
+}
+
+// SAFETY: the map owns its items, hasher and allocator, the same as if it held them in a `Box`.
+unsafe impl<K, V, S, A, P> Send for Map<K, V, S, A, P>
+where
+    K: Send,
+    V: Send,
+    S: BuildHasher + Send,
+    A: Allocator + Clone + Send,
+    P: GrowthPolicy,
+{
+}
+
+// SAFETY: `&Map` only gives out shared references to the items, hasher and allocator.
+unsafe impl<K, V, S, A, P> Sync for Map<K, V, S, A, P>
+where
+    K: Sync,
+    V: Sync,
+    S: BuildHasher + Sync,
+    A: Allocator + Clone + Sync,
+    P: GrowthPolicy,
+{
+}
+
+impl<K, V, S, A, P> Clone for Map<K, V, S, A, P>
+where
+    K: Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+    A: Allocator + Clone,
+    P: GrowthPolicy,
+{
+    fn clone(&self) -> Self {
+        let mut other = Self::with_buckets_and_hasher_in(
+            self.n_buckets,
+            self.hasher.clone(),
+            self.allocator.clone(),
+        );
+        assert_eq!(self.n_buckets, other.n_buckets);
+        self.clone_items_into(&mut other);
+        other
+    }
+}
+
+/// Copies the items into an empty map with the same number of buckets.
+///
+/// This is specialized so that `Copy` items (or anything else whose `Clone` is just a copy)
+/// can be copied all at once.
+trait CloneItems {
+    fn clone_items_into(&self, other: &mut Self);
+}
+
+impl<K, V, S, A, P> CloneItems for Map<K, V, S, A, P>
+where
+    K: Clone,
+    V: Clone,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    P: GrowthPolicy,
+{
+    default fn clone_items_into(&self, other: &mut Self) {
+        for (i, &m) in self.metadata().iter().enumerate().take(self.n_buckets) {
+            if metadata::is_full(m) {
+                unsafe {
+                    let (k, v) = (*self.bucket(i)).assume_init_ref();
+                    (*other.bucket(i)).write((k.clone(), v.clone()));
+                }
+
+                // Important: Only update the metadata after we successfully clone!
+                // If cloning panics, then updating the metadata before cloning
+                // leads to a read of uninitialized memory when `other` is dropped.
+                other.set_metadata(i, m);
+                other.n_items += 1;
+                other.n_occupied += 1;
             }
         }
     }
 }
 
+impl<K, V, S, A, P> CloneItems for Map<K, V, S, A, P>
+where
+    K: TrivialClone,
+    V: TrivialClone,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    P: GrowthPolicy,
+{
+    fn clone_items_into(&self, other: &mut Self) {
+        // Both maps share `EMPTY_GROUP`, which we can't write to.
+        if self.n_buckets == 0 {
+            return;
+        }
+
+        // SAFETY: both maps have the same number of buckets, so the metadata and the storage
+        // are the same lengths. Copying can't panic, and the items don't need to be dropped,
+        // so copying the tombstones and uninitialized buckets along with them is fine.
+        unsafe {
+            let metadata = self.metadata();
+            core::ptr::copy_nonoverlapping(
+                metadata.as_ptr(),
+                other.metadata.as_ptr(),
+                metadata.len(),
+            );
+            // The last bucket has the lowest address.
+            let last = self.n_buckets - 1;
+            core::ptr::copy_nonoverlapping(self.bucket(last), other.bucket(last), self.n_buckets);
+        }
+        other.n_items = self.n_items;
+        other.n_occupied = self.n_occupied;
+    }
+}
+
 impl<K, V> Map<K, V> {
     pub fn new() -> Self {
         Self::with_capacity(0)
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
-        let capacity = fix_capacity(capacity);
-        let allocator = Global::default();
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, hasher, Global)
+    }
+}
 
-        let (metadata, storage) = if capacity > 0 {
-            unsafe { allocate_for_capacity(&allocator, capacity) }
+impl<K, V, A: Allocator + Clone> Map<K, V, DefaultHashBuilder, A> {
+    pub fn new_in(allocator: A) -> Self {
+        Self::with_capacity_in(0, allocator)
+    }
+
+    pub fn with_capacity_in(capacity: usize, allocator: A) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, DefaultHashBuilder::default(), allocator)
+    }
+}
+
+impl<K, V, S: BuildHasher, A: Allocator + Clone, P: GrowthPolicy> Map<K, V, S, A, P> {
+    pub fn with_hasher_in(hasher: S, allocator: A) -> Self {
+        Self::with_capacity_and_hasher_in(0, hasher, allocator)
+    }
+
+    /// Creates a map that can hold at least `capacity` items without resizing.
+    pub fn with_capacity_and_hasher_in(capacity: usize, hasher: S, allocator: A) -> Self {
+        let n_buckets =
+            growth::capacity_to_buckets::<P>(capacity).unwrap_or_else(|e| handle_reserve_error(e));
+        Self::with_buckets_and_hasher_in(n_buckets, hasher, allocator)
+    }
+
+    /// Creates a map with `n_buckets` buckets, rounded up to what the policy allows.
+    fn with_buckets_and_hasher_in(n_buckets: usize, hasher: S, allocator: A) -> Self {
+        let capacity = whole_group(growth::fix_capacity::<P>(n_buckets));
+
+        let metadata = if capacity > 0 {
+            unsafe { allocate_for_capacity::<_, K, V>(&allocator, capacity) }
+                .unwrap_or_else(|e| handle_reserve_error(e))
         } else {
-            (NonNull::dangling(), NonNull::dangling())
+            empty_metadata()
         };
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             allocator,
             n_items: 0,
             n_occupied: 0,
             n_buckets: capacity,
-            storage,
             metadata,
-            _ph: std::marker::PhantomData,
+            _ph: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns a reference to the map's [`Allocator`].
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    /// The metadata for every bucket, followed by the mirrored tail group.
+    ///
+    /// If `self.n_buckets == 0`, this is just `EMPTY_GROUP`.
+    fn metadata(&self) -> &[Metadata] {
+        let len = self.n_buckets + TargetGroup::WIDTH;
+        // SAFETY: all of the metadata was initialized when it was allocated.
+        unsafe { slice::from_raw_parts(self.metadata.as_ptr(), len) }
+    }
+
+    /// A pointer to bucket `index`, counting backwards from the start of the metadata.
+    ///
+    /// SAFETY: `index < self.n_buckets`.
+    unsafe fn bucket(&self, index: usize) -> *mut MaybeUninit<(K, V)> {
+        self.metadata
+            .cast::<MaybeUninit<(K, V)>>()
+            .as_ptr()
+            .sub(index + 1)
+    }
+
+    fn set_metadata(&mut self, index: usize, value: Metadata) {
+        let index = fast_rem(index, self.n_buckets);
+        let index2 =
+            fast_rem(index.wrapping_sub(TargetGroup::WIDTH), self.n_buckets) + TargetGroup::WIDTH;
+        // SAFETY: both indices are less than `self.n_buckets + TargetGroup::WIDTH`.
+        unsafe {
+            *self.metadata.as_ptr().add(index) = value;
+            *self.metadata.as_ptr().add(index2) = value;
+        }
+    }
+
+    /// Move the item out of bucket `index`, leaving behind an empty bucket or a tombstone.
+    ///
+    /// SAFETY: `self.bucket(index)` must be initialized.
+    unsafe fn remove_at(&mut self, index: usize) -> (K, V) {
+        let kv = self.bucket(index).read().assume_init();
+
+        let metadata_value = self.decide_tombstone_or_empty(index);
+        self.set_metadata(index, metadata_value);
+
+        self.n_items -= 1;
+        // Branchless way of decrementing if `is_empty(metadata_value)`.
+        // `metadata::empty()` is 0x80, `metadata::tombstone()` is 0xFE.
+        self.n_occupied -= ((!metadata_value >> 6) & 0b1) as usize;
+        kv
+    }
+
+    /// We can set back to empty unless we're inside a run of `TargetGroup::WIDTH`
+    /// non-empty buckets.
+    fn decide_tombstone_or_empty(&self, index: usize) -> Metadata {
+        // Degenerate case where n_buckets is TargetGroup::WIDTH
+        if self.n_buckets == TargetGroup::WIDTH {
+            return metadata::empty();
         }
+
+        let metadata = self.metadata();
+        let probe_current = TargetGroup::from_slice(&metadata[index..]);
+        let next_empty =
+            group::find_first(probe_current.to_empties()).unwrap_or(TargetGroup::WIDTH);
+
+        let previous = fast_rem(index.wrapping_sub(TargetGroup::WIDTH), self.n_buckets);
+        let probe_previous = TargetGroup::from_slice(&metadata[previous..]);
+        let last_empty = group::find_last(probe_previous.to_empties()).unwrap_or(0);
+
+        // Find the distance between nearest two empty buckets.
+        // If it's less than TargetGroup::WIDTH, then all groups containing `index` have
+        // at least one empty bucket.
+        if likely((next_empty + TargetGroup::WIDTH).saturating_sub(last_empty) < TargetGroup::WIDTH)
+        {
+            metadata::empty()
+        } else {
+            metadata::tombstone()
+        }
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let metadata = &self.metadata()[..self.n_buckets];
+        metadata
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| metadata::is_full(m))
+            .map(move |(index, _)| unsafe {
+                // SAFETY: `index < self.n_buckets`, and `self.bucket(index)` is initialized
+                // whenever `metadata[index]` is full.
+                let (k, v) = (*self.bucket(index)).assume_init_ref();
+                (k, v)
+            })
     }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S, A, P> fmt::Debug for Map<K, V, S, A, P>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    P: GrowthPolicy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, S, A, P> Default for Map<K, V, S, A, P>
+where
+    S: BuildHasher + Default,
+    A: Allocator + Clone + Default,
+    P: GrowthPolicy,
+{
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher_in(S::default(), A::default())
     }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S, A, P> Map<K, V, S, A, P>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
+    A: Allocator + Clone,
+    P: GrowthPolicy,
 {
-    /// SAFETY: `self.metadata` and `self.storage` can't be null!
-    ///
-    /// Only call this if `self.n_buckets > 0`.
-    unsafe fn probe_find(&self, k: &K) -> ProbeResult {
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let (mut current, h2) = self.bucket_index_and_h2(k);
-        let initial_index = current;
-        let mut step = 1;
+        let metadata = self.metadata();
 
+        // There's always an empty bucket to stop at, even if there are no buckets at all
+        // (then `current` is 0 and we load `EMPTY_GROUP`).
+        let mut step = 0;
         loop {
-            let meta = unsafe { *self.metadata.as_ptr().offset(current as isize) };
+            current = fast_rem(current + step * TargetGroup::WIDTH, self.n_buckets);
+            step += 1;
+            let group = TargetGroup::from_slice(&metadata[current..]);
 
-            if metadata::is_empty(meta) {
-                return ProbeResult::Empty(current, h2);
-            } else if metadata::is_full(meta) && metadata::h2(meta) == h2 {
+            // First, check full buckets.
+            let candidates = group::MaskIter::forward(group.to_candidates(h2));
+            for i in candidates {
+                let index = fast_rem(current + i, self.n_buckets);
                 // SAFETY: we checked the invariant that `meta.is_value()`.
-                let (kk, _) =
-                    unsafe { (*self.storage.as_ptr().offset(current as isize)).assume_init_ref() };
-                if kk == k {
-                    return ProbeResult::Full(current);
+                let (kk, _) = unsafe { (*self.bucket(index)).assume_init_ref() };
+                if kk.borrow() == k {
+                    return ProbeResult::Full(index);
                 }
             }
 
-            current = usize::rem_euclid(current + step, self.n_buckets());
-            step += 1;
-
-            // We've seen every element in `storage`!
-            if current == initial_index {
-                return ProbeResult::End;
+            // If we've made it to here, our key isn't in this group.
+            // Look for the first empty bucket.
+            let empty = group::find_first(group.to_empties());
+            if let Some(i) = empty {
+                let index = fast_rem(current + i, self.n_buckets);
+                return ProbeResult::Empty(index, h2);
             }
         }
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
-        if self.n_buckets == 0 {
-            return None;
-        }
-        match unsafe { self.probe_find(k) } {
-            ProbeResult::Empty(..) | ProbeResult::End => None,
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
-                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
-                let (_, v) =
-                    unsafe { (*self.storage.as_ptr().offset(index as isize)).assume_init_ref() };
+                // SAFETY: `ProbeResult::Full` implies that `self.bucket(index)` is initialized.
+                let (_, v) = unsafe { (*self.bucket(index)).assume_init_ref() };
                 Some(v)
             }
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
-        if self.n_buckets == 0 {
-            return None;
-        }
-        match unsafe { self.probe_find(k) } {
-            ProbeResult::Empty(..) | ProbeResult::End => None,
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
-                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
-                let (_, v) =
-                    unsafe { (*self.storage.as_ptr().offset(index as isize)).assume_init_mut() };
+                // SAFETY: `ProbeResult::Full` implies that `self.bucket(index)` is initialized.
+                let (_, v) = unsafe { (*self.bucket(index)).assume_init_mut() };
                 Some(v)
             }
         }
     }
 
     pub fn insert(&mut self, k: K, v: V) -> Option<V> {
-        if self.needs_resize() {
+        if unlikely(self.needs_resize()) {
             self.resize();
         }
-        unsafe { self._insert(k, v) }
+        self._insert(k, v)
     }
 
-    /// SAFETY: `self.n_buckets > 0`, and `self.n_buckets` is big enough to hold the new item.
-    unsafe fn _insert(&mut self, k: K, v: V) -> Option<V> {
+    fn _insert(&mut self, k: K, v: V) -> Option<V> {
         match self.probe_find(&k) {
             ProbeResult::Empty(index, h2) => {
-                let index = index as isize;
-                std::ptr::write(self.metadata.as_ptr().offset(index), metadata::from_h2(h2));
-                (*self.storage.as_ptr().offset(index)).write((k, v));
+                self.set_metadata(index, metadata::from_h2(h2));
+                // SAFETY: `index < self.n_buckets`.
+                unsafe { (*self.bucket(index)).write((k, v)) };
                 self.n_items += 1;
                 self.n_occupied += 1;
                 None
             }
             ProbeResult::Full(index) => {
-                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
-                let (_, vv) = (*self.storage.as_ptr().offset(index as isize)).assume_init_mut();
-                Some(std::mem::replace(vv, v))
-            }
-            ProbeResult::End => {
-                panic!("backing storage is full, we didn't resize correctly")
+                // SAFETY: `ProbeResult::Full` implies that `self.bucket(index)` is initialized.
+                let (_, vv) = unsafe { (*self.bucket(index)).assume_init_mut() };
+                Some(core::mem::replace(vv, v))
             }
         }
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
-        if self.n_buckets == 0 {
-            return None;
-        }
-        match unsafe { self.probe_find(k) } {
-            ProbeResult::Empty(..) | ProbeResult::End => None,
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.probe_find(k) {
+            ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
-                let old_bucket = unsafe {
-                    std::ptr::replace(
-                        self.storage.as_ptr().offset(index as isize),
-                        MaybeUninit::uninit(),
-                    )
-                };
-                // SAFETY: `ProbeResult::Full` implies that `self.storage[index]` is initialized.
-                let (_, vv) = unsafe { old_bucket.assume_init() };
-                unsafe {
-                    std::ptr::write(
-                        self.metadata.as_ptr().offset(index as isize),
-                        metadata::tombstone(),
-                    );
-                }
-                self.n_items -= 1;
+                // SAFETY: `ProbeResult::Full` implies that `self.bucket(index)` is initialized.
+                let (_, vv) = unsafe { self.remove_at(index) };
+                self.shrink_if_sparse();
                 Some(vv)
             }
         }
@@ -271,73 +594,278 @@ where
         self.n_buckets
     }
 
-    fn bucket_index_and_h2(&self, k: &K) -> (usize, u8) {
+    fn bucket_index_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> (usize, u8) {
         let hash = make_hash(&self.hasher, k);
         let (h1, h2) = (hash >> 7, (hash & 0x7F) as u8);
-        let index = usize::rem_euclid(h1 as usize, self.n_buckets());
+        let index = fast_rem(h1 as usize, self.n_buckets());
         (index, h2)
     }
 
+    /// Returns statistics about how the items are laid out in the buckets.
+    ///
+    /// This looks at every bucket and hashes every key, so it's slow.
+    pub fn stats(&self) -> MapStats {
+        let n_buckets = self.n_buckets();
+        let metadata = &self.metadata()[..n_buckets];
+        let probes = metadata
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| metadata::is_full(m))
+            .map(|(i, _)| {
+                // SAFETY: `self.bucket(i)` is initialized since `metadata[i]` is full.
+                let (k, _) = unsafe { (*self.bucket(i)).assume_init_ref() };
+                let (home, _) = self.bucket_index_and_h2(k);
+                stats::group_probe_length(home, i, n_buckets, TargetGroup::WIDTH)
+            });
+        MapStats::new(metadata, TargetGroup::WIDTH, probes)
+    }
+
     fn needs_resize(&self) -> bool {
-        // Using a load factor of 7/8.
         // NOTE: we need to use n_occupied instead of n_items here!
-        self.n_buckets() == 0 || ((self.n_occupied * 8) / self.n_buckets()) > 7
+        self.n_occupied >= growth::bucket_capacity::<P>(self.n_buckets())
     }
 
     fn resize(&mut self) {
-        let old_capacity = self.n_buckets();
-
         // Calculate the new capacity.
-        let capacity = match old_capacity {
-            0 => 16,
-            x => x * 2,
+        let capacity = match self.n_buckets() {
+            0 => whole_group(P::MIN_BUCKETS),
+            x => growth::grow::<P>(x),
         };
+        if let Err(e) = self.try_resize(capacity) {
+            handle_reserve_error(e);
+        }
+    }
+
+    /// Move all of the items into a new allocation with `capacity` buckets.
+    ///
+    /// `capacity` must be big enough to hold all of the items.
+    fn try_resize(&mut self, capacity: usize) -> Result<(), TryReserveError> {
+        let old_capacity = self.n_buckets();
 
-        let (new_metadata, new_storage) =
-            unsafe { allocate_for_capacity(&self.allocator, capacity) };
+        let new_metadata = if capacity > 0 {
+            unsafe { allocate_for_capacity::<_, K, V>(&self.allocator, capacity)? }
+        } else {
+            empty_metadata()
+        };
 
+        let old_metadata = core::mem::replace(&mut self.metadata, new_metadata);
         self.n_buckets = capacity;
         self.n_items = 0;
         self.n_occupied = 0;
 
         // Make sure to early return if our old capacity was zero!
         if old_capacity == 0 {
-            self.metadata = new_metadata;
-            self.storage = new_storage;
-            return;
+            return Ok(());
+        }
+
+        // Walk the old buckets a group at a time, stopping before the replicated metadata group.
+        let old_buckets = old_metadata.cast::<MaybeUninit<(K, V)>>();
+        for start in (0..old_capacity).step_by(TargetGroup::WIDTH) {
+            // SAFETY: the old metadata has `old_capacity + TargetGroup::WIDTH` initialized
+            // elements, so there's a whole group starting at `start`.
+            let group = unsafe {
+                slice::from_raw_parts(old_metadata.as_ptr().add(start), TargetGroup::WIDTH)
+            };
+            // Get a mask showing the indices with full buckets, and re-insert each of them.
+            let fulls = TargetGroup::from_slice(group).to_fulls();
+            for i in group::MaskIter::forward(fulls) {
+                // SAFETY: full buckets are initialized according to our safety invariant,
+                // and we only read each one once.
+                let (k, v) = unsafe { old_buckets.sub(start + i + 1).read().assume_init() };
+                self._insert(k, v);
+            }
         }
 
-        // Set `self.storage` to a new array.
-        //
-        // Trying to use `std::ptr::swap` here is UB :(
-        let old_metadata = self.metadata;
-        let old_storage = self.storage;
-        self.metadata = new_metadata;
-        self.storage = new_storage;
+        unsafe {
+            deallocate_for_capacity::<_, K, V>(&self.allocator, old_metadata, old_capacity);
+        }
+        Ok(())
+    }
 
-        // Move nodes from `old_storage` to `self.storage`.
-        for offset in 0..(old_capacity as isize) {
-            unsafe {
-                let metadata = old_metadata.as_ptr().offset(offset);
-                let storage = old_storage.as_ptr().offset(offset);
+    /// Reserves capacity for at least `additional` more items to be inserted without resizing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new capacity overflows `usize`, and aborts on allocation failure.
+    pub fn reserve(&mut self, additional: usize) {
+        if let Err(e) = self.try_reserve(additional) {
+            handle_reserve_error(e);
+        }
+    }
 
-                if metadata::is_full(*metadata) {
-                    // SAFETY: we just checked the invariant above.
-                    let (k, v) = std::ptr::read(storage).assume_init();
-                    self._insert(k, v);
+    /// Tries to reserve capacity for at least `additional` more items to be inserted without
+    /// resizing. On error, the map is left unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        // Tombstones take up room too, since new items are only placed in empty buckets.
+        let needed = self
+            .n_occupied
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if needed <= growth::bucket_capacity::<P>(self.n_buckets()) {
+            return Ok(());
+        }
+
+        // Resizing gets rid of all of the tombstones, so we only need room for the live items.
+        let capacity = whole_group(growth::capacity_to_buckets::<P>(self.n_items + additional)?)
+            .max(self.n_buckets());
+        self.try_resize(capacity)
+    }
+
+    /// Shrinks the map to fit its items if the growth policy says it's gotten too empty.
+    fn shrink_if_sparse(&mut self) {
+        if unlikely(growth::should_shrink::<P>(self.n_items, self.n_buckets())) {
+            let capacity = growth::capacity_to_buckets::<P>(self.n_items)
+                .map(|n| whole_group(n.max(P::MIN_BUCKETS)))
+                .unwrap_or_else(|e| handle_reserve_error(e));
+            if capacity < self.n_buckets() {
+                if let Err(e) = self.try_resize(capacity) {
+                    handle_reserve_error(e);
                 }
             }
         }
+    }
 
-        let (old_layout, _) = layout_for_capacity::<K, V>(old_capacity);
-        unsafe {
-            self.allocator.deallocate(old_metadata.cast(), old_layout);
+    /// Shrinks the capacity of the map as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Shrinks the capacity of the map to hold at least `min_capacity` items,
+    /// while keeping room for the items already in the map.
+    ///
+    /// If the current capacity is already smaller than this, this does nothing.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let capacity = growth::capacity_to_buckets::<P>(min_capacity.max(self.n_items))
+            .map(whole_group)
+            .unwrap_or_else(|e| handle_reserve_error(e));
+        if capacity < self.n_buckets() {
+            if let Err(e) = self.try_resize(capacity) {
+                handle_reserve_error(e);
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    // use crate::sixth::Map;
-    // crate::generate_tests!(Map, true);
+    use crate::sixth::Map;
+    crate::generate_tests!(Map, false);
+    crate::generate_reserve_tests!(Map);
+    crate::generate_stats_tests!(Map);
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Map<String, Vec<u8>>>();
+    }
+
+    #[test]
+    fn clone_copy_items() {
+        let mut map = Map::new();
+        for i in 0..1000 {
+            map.insert(i, i * 2);
+        }
+        for i in (0..1000).step_by(3) {
+            map.remove(&i);
+        }
+
+        let cloned = map.clone();
+        assert_eq!(cloned.len(), map.len());
+        assert_eq!(cloned.n_occupied, map.n_occupied);
+        for i in 0..1000 {
+            assert_eq!(cloned.get(&i), map.get(&i));
+        }
+    }
+
+    #[test]
+    fn debug() {
+        let mut map = Map::new();
+        map.insert(1, "one");
+        assert_eq!(format!("{map:?}"), r#"{1: "one"}"#);
+        assert_eq!(format!("{:?}", Map::<u8, u8>::new()), "{}");
+    }
+
+    #[test]
+    fn empty_map_doesnt_allocate() {
+        use crate::testing::CountingAllocator;
+
+        let alloc = CountingAllocator::default();
+        let mut map: Map<usize, usize, _, _> = Map::new_in(alloc.clone());
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.remove(&1), None);
+        assert_eq!(map.iter().count(), 0);
+        // Cloning mustn't write to the shared empty group.
+        let cloned = map.clone();
+        assert!(cloned.is_empty());
+        assert_eq!(alloc.0.get(), 0);
+
+        map.insert(1, 1);
+        assert!(alloc.0.get() > 0);
+        map.remove(&1);
+        map.shrink_to_fit();
+        assert_eq!(map.n_buckets(), 0);
+        assert_eq!(alloc.0.get(), 0);
+        assert_eq!(map.get(&1), None);
+    }
+
+    #[test]
+    fn custom_allocator() {
+        use crate::testing::CountingAllocator;
+
+        let alloc = CountingAllocator::default();
+        let mut map = Map::with_capacity_in(100, alloc.clone());
+        assert!(alloc.0.get() > 0);
+
+        for i in 0..1000 {
+            map.insert(i, i.to_string());
+        }
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i.to_string()));
+        }
+
+        drop(map);
+        assert_eq!(alloc.0.get(), 0);
+
+        let map: Map<usize, usize, _, _> = Map::new_in(alloc.clone());
+        assert_eq!(alloc.0.get(), 0);
+        assert_eq!(map.allocator().0.get(), 0);
+    }
+
+    #[test]
+    fn growth_policy() {
+        use std::alloc::Global;
+
+        use crate::testing::{Dense, Shrinking};
+        use crate::DefaultHashBuilder;
+
+        // With a load factor of 15/16, 16 buckets hold 15 items instead of 14.
+        let mut dense: Map<usize, usize, DefaultHashBuilder, Global, Dense> =
+            Map::with_capacity_and_hasher_in(15, DefaultHashBuilder::default(), Global);
+        for i in 0..15 {
+            dense.insert(i, i);
+        }
+        assert_eq!(dense.n_buckets(), 16);
+
+        let mut shrinking: Map<usize, usize, DefaultHashBuilder, Global, Shrinking> =
+            Map::default();
+        for i in 0..1000 {
+            shrinking.insert(i, i);
+        }
+        let buckets = shrinking.n_buckets();
+        for i in 0..900 {
+            shrinking.remove(&i);
+        }
+        assert!(shrinking.n_buckets() < buckets);
+        for i in 0..1000 {
+            assert_eq!(shrinking.get(&i), (i >= 900).then_some(&i));
+        }
+
+        // It never shrinks below the minimum, so that removing the last item doesn't
+        // give back the allocation.
+        for i in 900..1000 {
+            shrinking.remove(&i);
+        }
+        assert!(shrinking.is_empty());
+        assert_eq!(shrinking.n_buckets(), 16);
+    }
 }
@@ -0,0 +1,221 @@
+//! Statistics about how the items in a Swiss table are laid out.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fast_rem;
+use crate::metadata::{self, Metadata};
+
+/// A snapshot of the structure of a map, returned by `stats()`.
+///
+/// This is for tracking down performance problems: lots of tombstones mean that the map should
+/// be rehashed, long probes mean that items are clustering, and a high
+/// [`h2_false_positive_rate`](Self::h2_false_positive_rate) means that the hash is bad.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapStats {
+    /// The total number of buckets.
+    pub n_buckets: usize,
+    /// The number of items in the map.
+    pub n_items: usize,
+    /// The number of buckets that aren't empty, so items plus tombstones.
+    pub n_occupied: usize,
+    /// The number of tombstones left behind by removed items.
+    pub n_tombstones: usize,
+    /// The fraction of buckets that are occupied, which is what decides when the map grows.
+    pub load_factor: f64,
+    /// The number of buckets in a group, for the histograms below.
+    ///
+    /// Maps that probe one bucket at a time use the width of
+    /// [`TargetGroup`](crate::group::TargetGroup), so that they can be compared with the ones
+    /// that don't.
+    pub group_width: usize,
+    /// `probe_lengths[n]` is the number of items that a lookup finds after looking at
+    /// `n + 1` buckets.
+    pub probe_lengths: Vec<usize>,
+    /// `group_probe_lengths[n]` is the number of items that a lookup finds after loading
+    /// `n + 1` groups.
+    pub group_probe_lengths: Vec<usize>,
+    /// `fulls_per_group[n]` is the number of groups with exactly `n` items.
+    pub fulls_per_group: Vec<usize>,
+    /// `empties_per_group[n]` is the number of groups with exactly `n` empty buckets.
+    pub empties_per_group: Vec<usize>,
+    /// `tombstones_per_group[n]` is the number of groups with exactly `n` tombstones.
+    pub tombstones_per_group: Vec<usize>,
+    /// The chance that a full bucket's `h2` matches the `h2` of a different key,
+    /// so that the key has to be compared for nothing.
+    ///
+    /// This is estimated from the `h2`s of the items in the map. With a good hash it's close
+    /// to 1/128, and it gets bigger as the hashes get more alike.
+    pub h2_false_positive_rate: f64,
+}
+
+impl MapStats {
+    /// Builds the statistics from the metadata of each bucket (without any mirrored tail),
+    /// and the number of buckets and groups that a lookup looks at to find each item.
+    pub(crate) fn new(
+        metadata: &[Metadata],
+        group_width: usize,
+        probes: impl IntoIterator<Item = (usize, usize)>,
+    ) -> Self {
+        let n_buckets = metadata.len();
+        let n_items = metadata.iter().filter(|&&m| metadata::is_full(m)).count();
+        let n_tombstones = metadata
+            .iter()
+            .filter(|&&m| m == metadata::tombstone())
+            .count();
+        let n_occupied = n_items + n_tombstones;
+
+        let mut probe_lengths = vec![];
+        let mut group_probe_lengths = vec![];
+        for (buckets, groups) in probes {
+            increment(&mut probe_lengths, buckets - 1);
+            increment(&mut group_probe_lengths, groups - 1);
+        }
+
+        let mut fulls_per_group = vec![0; group_width + 1];
+        let mut empties_per_group = vec![0; group_width + 1];
+        let mut tombstones_per_group = vec![0; group_width + 1];
+        for group in metadata.chunks(group_width) {
+            let count = |f: fn(Metadata) -> bool| group.iter().filter(|&&m| f(m)).count();
+            fulls_per_group[count(metadata::is_full)] += 1;
+            empties_per_group[count(metadata::is_empty)] += 1;
+            tombstones_per_group[count(|m| m == metadata::tombstone())] += 1;
+        }
+
+        let mut h2s = [0usize; 128];
+        for &m in metadata.iter().filter(|&&m| metadata::is_full(m)) {
+            h2s[metadata::h2(m) as usize] += 1;
+        }
+        // The chance that two different items picked at random have the same `h2`.
+        let h2_false_positive_rate = if n_items < 2 {
+            0.0
+        } else {
+            let same_pairs: usize = h2s.iter().map(|&n| n * n.saturating_sub(1)).sum();
+            same_pairs as f64 / (n_items * (n_items - 1)) as f64
+        };
+
+        Self {
+            n_buckets,
+            n_items,
+            n_occupied,
+            n_tombstones,
+            load_factor: if n_buckets == 0 {
+                0.0
+            } else {
+                n_occupied as f64 / n_buckets as f64
+            },
+            group_width,
+            probe_lengths,
+            group_probe_lengths,
+            fulls_per_group,
+            empties_per_group,
+            tombstones_per_group,
+            h2_false_positive_rate,
+        }
+    }
+}
+
+fn increment(histogram: &mut Vec<usize>, index: usize) {
+    if histogram.len() <= index {
+        histogram.resize(index + 1, 0);
+    }
+    histogram[index] += 1;
+}
+
+/// The number of buckets and groups that a lookup looks at to find the item in bucket `index`,
+/// for maps that probe one bucket at a time starting from bucket `home`.
+pub(crate) fn bucket_probe_length(
+    home: usize,
+    index: usize,
+    n_buckets: usize,
+    group_width: usize,
+) -> (usize, usize) {
+    let mut current = home;
+    for step in 0..n_buckets {
+        current = fast_rem(current + step, n_buckets);
+        if current == index {
+            return (step + 1, (step + 1).div_ceil(group_width));
+        }
+    }
+    unreachable!("bucket {index} isn't in the probe sequence")
+}
+
+/// The number of buckets and groups that a lookup looks at to find the item in bucket `index`,
+/// for maps that probe one group at a time starting from bucket `home`.
+pub(crate) fn group_probe_length(
+    home: usize,
+    index: usize,
+    n_buckets: usize,
+    group_width: usize,
+) -> (usize, usize) {
+    let mut current = home;
+    for step in 0..n_buckets {
+        current = fast_rem(current + step * group_width, n_buckets);
+        let offset = fast_rem(index.wrapping_sub(current), n_buckets);
+        if offset < group_width {
+            return (step * group_width + offset + 1, step + 1);
+        }
+    }
+    unreachable!("bucket {index} isn't in the probe sequence")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_probe_length, group_probe_length, MapStats};
+    use crate::metadata;
+
+    #[test]
+    fn probe_lengths() {
+        // Probing one bucket at a time visits 5, 6, 8, 11, 15, 4, ...
+        assert_eq!(bucket_probe_length(5, 5, 16, 4), (1, 1));
+        assert_eq!(bucket_probe_length(5, 11, 16, 4), (4, 1));
+        assert_eq!(bucket_probe_length(5, 4, 16, 4), (6, 2));
+
+        // Probing a group at a time visits 14..18, 2..6, 10..14, ...
+        assert_eq!(group_probe_length(14, 15, 16, 4), (2, 1));
+        assert_eq!(group_probe_length(14, 1, 16, 4), (4, 1));
+        assert_eq!(group_probe_length(14, 2, 16, 4), (5, 2));
+        assert_eq!(group_probe_length(14, 12, 16, 4), (11, 3));
+    }
+
+    #[test]
+    fn counts() {
+        let full = metadata::from_h2(3);
+        let empty = metadata::empty();
+        let tombstone = metadata::tombstone();
+        let meta = [
+            full, full, empty, tombstone, //
+            empty, empty, empty, empty, //
+            full, tombstone, tombstone, full,
+        ];
+        let stats = MapStats::new(&meta, 4, [(1, 1), (1, 1), (2, 1), (6, 2)]);
+
+        assert_eq!(stats.n_buckets, 12);
+        assert_eq!(stats.n_items, 4);
+        assert_eq!(stats.n_tombstones, 3);
+        assert_eq!(stats.n_occupied, 7);
+        assert_eq!(stats.load_factor, 7.0 / 12.0);
+        assert_eq!(stats.probe_lengths, [2, 1, 0, 0, 0, 1]);
+        assert_eq!(stats.group_probe_lengths, [3, 1]);
+        assert_eq!(stats.fulls_per_group, [1, 0, 2, 0, 0]);
+        assert_eq!(stats.empties_per_group, [1, 1, 0, 0, 1]);
+        assert_eq!(stats.tombstones_per_group, [1, 1, 1, 0, 0]);
+        // Every item has the same `h2`.
+        assert_eq!(stats.h2_false_positive_rate, 1.0);
+    }
+
+    #[test]
+    fn h2_false_positive_rate() {
+        let rate = |h2s: &[u8]| {
+            let meta: Vec<_> = h2s.iter().map(|&h2| metadata::from_h2(h2)).collect();
+            MapStats::new(&meta, 4, []).h2_false_positive_rate
+        };
+
+        assert_eq!(rate(&[]), 0.0);
+        assert_eq!(rate(&[7]), 0.0);
+        // An item doesn't count as matching itself.
+        assert_eq!(rate(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]), 0.0);
+        // 4 of the 12 ordered pairs match.
+        assert_eq!(rate(&[1, 1, 2, 2]), 1.0 / 3.0);
+    }
+}
@@ -1,10 +1,15 @@
 //! A Swiss Tables-inspired map with metadata.
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
 use core::hash::{BuildHasher, Hash};
 
 use crate::{fast_rem, fix_capacity, make_hash, DefaultHashBuilder};
 
+use crate::group::{Group, TargetGroup};
 use crate::metadata::{self, Metadata};
+use crate::stats::{self, MapStats};
 
 enum ProbeResult {
     Empty(usize, u8),
@@ -26,6 +31,16 @@ impl<K, V> Map<K, V> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S: BuildHasher> Map<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         let capacity = fix_capacity(capacity);
 
         let storage = (0..capacity).map(|_| None).collect();
@@ -33,22 +48,27 @@ impl<K, V> Map<K, V> {
         let metadata = (0..capacity).map(|_| metadata::empty()).collect();
 
         Self {
-            hasher: DefaultHashBuilder::default(),
+            hasher,
             n_items: 0,
             n_occupied: 0,
             storage,
             metadata,
         }
     }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
 }
 
-impl<K, V> Default for Map<K, V> {
+impl<K, V, S: BuildHasher + Default> Default for Map<K, V, S> {
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher(S::default())
     }
 }
 
-impl<K, V> Map<K, V> {
+impl<K, V, S: BuildHasher> Map<K, V, S> {
     pub fn len(&self) -> usize {
         self.n_items
     }
@@ -57,18 +77,30 @@ impl<K, V> Map<K, V> {
         self.n_items == 0
     }
 
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.storage
+            .iter()
+            .filter_map(|bucket| bucket.as_ref().map(|(k, v)| (k, v)))
+    }
+
     /// Used for testing
     #[inline]
-    fn n_buckets(&self) -> usize {
+    pub(crate) fn n_buckets(&self) -> usize {
         self.storage.len()
     }
 }
 
-impl<K, V> Map<K, V>
+impl<K, V, S> Map<K, V, S>
 where
     K: PartialEq + Eq + Hash,
+    S: BuildHasher,
 {
-    fn probe_find(&self, k: &K) -> ProbeResult {
+    fn probe_find<Q>(&self, k: &Q) -> ProbeResult
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let (mut current, h2) = self.bucket_index_and_h2(k);
 
         for step in 0..self.n_buckets() {
@@ -79,7 +111,7 @@ where
                 return ProbeResult::Empty(current, h2);
             } else if metadata::is_full(meta) && metadata::h2(meta) == h2 {
                 let (kk, _) = self.storage[current].as_ref().unwrap();
-                if kk == k {
+                if kk.borrow() == k {
                     return ProbeResult::Full(current);
                 }
             }
@@ -92,14 +124,30 @@ where
         }
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => self.storage[index].as_ref().map(|(_, v)| v),
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => self.storage[index].as_mut().map(|(_, v)| v),
@@ -124,12 +172,16 @@ where
             }
             ProbeResult::Full(index) => {
                 let (_, vv) = self.storage[index].as_mut().unwrap();
-                Some(std::mem::replace(vv, v))
+                Some(core::mem::replace(vv, v))
             }
         }
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V> {
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         match self.probe_find(k) {
             ProbeResult::Empty(..) => None,
             ProbeResult::Full(index) => {
@@ -141,13 +193,31 @@ where
         }
     }
 
-    fn bucket_index_and_h2(&self, k: &K) -> (usize, u8) {
+    fn bucket_index_and_h2<Q: ?Sized + Hash>(&self, k: &Q) -> (usize, u8) {
         let hash = make_hash(&self.hasher, k);
         let (h1, h2) = (hash >> 7, (hash & 0x7F) as u8);
         let index = fast_rem(h1 as usize, self.n_buckets());
         (index, h2)
     }
 
+    /// Returns statistics about how the items are laid out in the buckets.
+    ///
+    /// This looks at every bucket and hashes every key, so it's slow.
+    pub fn stats(&self) -> MapStats {
+        let n_buckets = self.n_buckets();
+        let probes = self.storage.iter().enumerate().filter_map(|(i, bucket)| {
+            let (k, _) = bucket.as_ref()?;
+            let (home, _) = self.bucket_index_and_h2(k);
+            Some(stats::bucket_probe_length(
+                home,
+                i,
+                n_buckets,
+                TargetGroup::WIDTH,
+            ))
+        });
+        MapStats::new(&self.metadata, TargetGroup::WIDTH, probes)
+    }
+
     fn needs_resize(&self) -> bool {
         // Using a load factor of 7/8.
         // NOTE: we need to use n_occupied instead of n_items here!
@@ -163,7 +233,7 @@ where
 
         // Set `self.storage` to a new array.
         let new_storage = (0..capacity).map(|_| None).collect();
-        let old_storage = std::mem::replace(&mut self.storage, new_storage);
+        let old_storage = core::mem::replace(&mut self.storage, new_storage);
 
         // We can throw away the old metadata, we need to recompute it anyway.
         self.metadata = (0..capacity).map(|_| metadata::empty()).collect();
@@ -183,5 +253,6 @@ where
 mod tests {
     use crate::third::Map;
     crate::generate_tests!(Map, true);
+    crate::generate_stats_tests!(Map);
     crate::generate_non_alloc_tests!(Map);
 }